@@ -0,0 +1,38 @@
+CREATE TABLE IF NOT EXISTS anilist_staff (
+    id INTEGER PRIMARY KEY NOT NULL,
+    name_full TEXT,
+    name_native TEXT,
+    site_url TEXT,
+    description TEXT,
+    image_large TEXT,
+    fetched_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS anilist_media (
+    id INTEGER PRIMARY KEY NOT NULL,
+    title_romaji TEXT,
+    title_english TEXT,
+    title_native TEXT,
+    media_type TEXT,
+    format TEXT,
+    status TEXT,
+    description TEXT,
+    site_url TEXT,
+    start_year INTEGER,
+    start_month INTEGER,
+    start_day INTEGER,
+    updated_at TEXT NOT NULL
+);
+
+-- One row per (staff, media, role) credit. first_seen_at is never updated so
+-- it records when a credit first appeared on Anilist; last_seen_at matches
+-- anilist_staff.fetched_at while the credit is still listed.
+CREATE TABLE IF NOT EXISTS anilist_staff_media_roles (
+    staff_id INTEGER NOT NULL REFERENCES anilist_staff (id),
+    media_id INTEGER NOT NULL REFERENCES anilist_media (id),
+    role TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    first_seen_at TEXT NOT NULL,
+    last_seen_at TEXT NOT NULL,
+    PRIMARY KEY (staff_id, media_id, role)
+);
@@ -0,0 +1,13 @@
+-- Credits first seen on a staff's very first fetch were already on Anilist
+-- before we started following them, so they can't be dated by discovery.
+ALTER TABLE anilist_staff ADD COLUMN first_fetched_at TEXT;
+
+UPDATE anilist_staff
+SET first_fetched_at = COALESCE(
+    (
+        SELECT MIN(r.first_seen_at)
+        FROM anilist_staff_media_roles r
+        WHERE r.staff_id = anilist_staff.id
+    ),
+    fetched_at
+);
@@ -0,0 +1,14 @@
+-- One row per status or start date change noticed when a stored media is
+-- fetched again. Nothing is written when a media is first stored, so rows are
+-- real transitions rather than the state Anilist already had.
+CREATE TABLE IF NOT EXISTS anilist_media_changes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    media_id INTEGER NOT NULL REFERENCES anilist_media (id),
+    kind TEXT NOT NULL,
+    old_value TEXT,
+    new_value TEXT,
+    changed_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS anilist_media_changes_media_id
+    ON anilist_media_changes (media_id);
@@ -0,0 +1,18 @@
+-- Named sets of staff served as one combined feed. feed_options holds the
+-- feed query string, e.g. include_role=Director, and only a SHA-256 hash of
+-- the edit token is kept.
+CREATE TABLE IF NOT EXISTS bundles (
+    slug TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    feed_options TEXT NOT NULL,
+    edit_token_sha256 TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS bundle_staff (
+    bundle_slug TEXT NOT NULL REFERENCES bundles (slug) ON DELETE CASCADE,
+    staff_id INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    PRIMARY KEY (bundle_slug, staff_id)
+);
@@ -0,0 +1,7 @@
+-- When media without an announced start year were first seen in any feed, so
+-- feeds that don't store what they show can still date them the same way on
+-- every build. Rows are never updated.
+CREATE TABLE IF NOT EXISTS anilist_undated_media (
+    media_id INTEGER PRIMARY KEY NOT NULL,
+    first_seen_at TEXT NOT NULL
+);
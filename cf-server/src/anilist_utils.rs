@@ -0,0 +1,2058 @@
+use crate::{
+    clients::{
+        aired_episodes_query,
+        character_media_query::{
+            CharacterMediaQueryCharacter, CharacterMediaQueryCharacterMediaEdges,
+            CharacterMediaQueryCharacterMediaNodes,
+        },
+        staff_character_media_query::{
+            StaffCharacterMediaQueryStaff, StaffCharacterMediaQueryStaffCharacterMediaEdges,
+            StaffCharacterMediaQueryStaffCharacterMediaNodes,
+        },
+        staff_media_query::{
+            StaffMediaQueryStaff, StaffMediaQueryStaffStaffMediaEdges,
+            StaffMediaQueryStaffStaffMediaNodes,
+        },
+        studio_media_query::{
+            StudioMediaQueryStudio, StudioMediaQueryStudioMediaEdges,
+            StudioMediaQueryStudioMediaNodes,
+        },
+        AnilistClient, CacheMode,
+    },
+    errors,
+    errors::ServiceError,
+    feeds::{DatePrecision, Feed, FeedItem},
+    filters::{MediaFilter, RoleFilter},
+    models::{
+        AiredEpisode, Character, CharacterAppearance, CharacterAppearances, CharacterCredit,
+        FuzzyDate, Media, MediaChange, MediaChangeKind, MediaCredit, Staff, StaffCredit,
+        StaffFilmography, Studio, StudioCredit, StudioFilmography, VoiceActor,
+    },
+    repository::AnilistRepository,
+};
+use chrono::{naive::NaiveDate, DateTime, Duration, Utc};
+use futures::{stream, StreamExt, TryStreamExt};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+
+const NO_STAFF_DESCRIPTION: &str = "No description provided by Anilist for this staff.";
+const NO_CHARACTER_DESCRIPTION: &str = "No description provided by Anilist for this character.";
+const NO_MEDIA_TITLE: &str = "Anilist has no title";
+const NO_CHARACTER_NAME: &str = "Unnamed character";
+const STAFF_NONE: &str = "Staff is None";
+const STUDIO_NONE: &str = "Studio is None";
+const CHARACTER_NONE: &str = "Character is None";
+const STAFF_MEDIA_BATCH_SIZE: i64 = 25;
+/// Anilist's page size limit for `Staff.characterMedia`.
+const CHARACTER_MEDIA_BATCH_SIZE: i64 = 25;
+/// Anilist's page size limit, both for episodes per page and media per query.
+const AIRED_EPISODES_BATCH_SIZE: i64 = 50;
+/// Only episodes this recent are asked for, so a long running anime costs a
+/// few requests rather than one per 50 episodes it ever aired.
+const AIRED_EPISODES_WINDOW_DAYS: i64 = 90;
+/// Most episode pages asked for per batch of media, newest first.
+const MAX_AIRED_EPISODES_PAGES: i64 = 4;
+/// Most episode items one anime gets in a feed.
+const MAX_AIRED_EPISODES_PER_MEDIA: usize = 12;
+/// Anilist's page size limit for `Studio.media`.
+const STUDIO_MEDIA_BATCH_SIZE: i64 = 25;
+/// Upper bound on staffMedia pages requested from Anilist at once, kept low so
+/// a single prolific staff doesn't use up the per minute rate limit.
+const STAFF_MEDIA_PAGE_CONCURRENCY: usize = 4;
+/// Most staff one combined feed may follow, so a single feed request can't set
+/// off an unbounded number of Anilist fetches.
+const MAX_COMBINED_STAFF: usize = 50;
+const COMBINED_STAFF_CONCURRENCY: usize = 4;
+/// Staff names spelled out in a combined feed's title before "and N more".
+const COMBINED_TITLE_NAMES: usize = 3;
+/// Feed image for feeds with no picture of their own, i.e. combined and studio
+/// feeds.
+const ANILIST_ICON_URL: &str = "https://anilist.co/img/icons/android-chrome-512x512.png";
+
+type RoleMedia = (
+    Option<StaffMediaQueryStaffStaffMediaEdges>,
+    Option<StaffMediaQueryStaffStaffMediaNodes>,
+);
+
+type CharacterMedia = (
+    Option<StaffCharacterMediaQueryStaffCharacterMediaEdges>,
+    Option<StaffCharacterMediaQueryStaffCharacterMediaNodes>,
+);
+
+type Appearance = (
+    Option<CharacterMediaQueryCharacterMediaEdges>,
+    Option<CharacterMediaQueryCharacterMediaNodes>,
+);
+
+type StudioMedia = (
+    Option<StudioMediaQueryStudioMediaEdges>,
+    Option<StudioMediaQueryStudioMediaNodes>,
+);
+
+/// One page of an Anilist connection, e.g. a staff's media, with its edges
+/// paired up with their nodes, along with the object it was asked through.
+struct ConnectionPage<P, T> {
+    parent: P,
+    last_page: i64,
+    entries: Vec<T>,
+}
+
+/// Every role a staff has on one media, or a single role when items aren't
+/// merged.
+struct AnilistMedia {
+    roles: Vec<String>,
+    media: Media,
+    first_seen_at: Option<DateTime<Utc>>,
+}
+
+/// Best-effort item date along with how much of it Anilist actually knows.
+struct ItemDate {
+    published: DateTime<Utc>,
+    precision: DatePrecision,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct StaffFeedOptions {
+    #[serde(flatten)]
+    media: MediaFeedOptions,
+    /// Comma separated substrings, at least one of which a role must contain.
+    include_role: Option<String>,
+    /// Comma separated substrings that drop a role when it contains any of them.
+    exclude_role: Option<String>,
+    include_role_regex: Option<String>,
+    exclude_role_regex: Option<String>,
+    /// Emits one item per role instead of merging every role a staff has on
+    /// the same media into a single item. Defaults to false.
+    one_item_per_role: Option<bool>,
+    /// What items are dated and ordered by. Defaults to the media start date.
+    order_by: Option<FeedOrder>,
+    /// Adds an item whenever a media's status or start date changes, e.g. when
+    /// it starts airing. Defaults to true.
+    include_changes: Option<bool>,
+    /// Production credits, characters voiced, or both. Defaults to production.
+    credits: Option<CreditKinds>,
+    /// Replaces the item of an anime that is airing or yet to air with one item
+    /// per recently aired episode, dated by when it aired. Defaults to false.
+    episodes: Option<bool>,
+}
+
+/// Which of a staff's credits a feed covers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CreditKinds {
+    /// Roles from `Staff.staffMedia`, e.g. "Director".
+    #[default]
+    Production,
+    /// Characters voiced, from `Staff.characterMedia`. Voice actors have few
+    /// production credits, so this is where most of their work is.
+    Character,
+    Both,
+}
+
+impl CreditKinds {
+    fn includes_production(self) -> bool {
+        self != CreditKinds::Character
+    }
+
+    fn includes_characters(self) -> bool {
+        self != CreditKinds::Production
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedOrder {
+    #[default]
+    StartDate,
+    /// When a credit first showed up on Anilist, so new credits on old media
+    /// show up at the top of the feed. Credits that were already listed when
+    /// the staff was first fetched keep their start date.
+    Discovered,
+}
+
+/// Media filters every feed has.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct MediaFeedOptions {
+    /// Keeps media whose start date is missing a day, month or year. Defaults
+    /// to true.
+    #[serde(default, deserialize_with = "deserialize_query_bool")]
+    include_undated: Option<bool>,
+    /// Comma separated Anilist `MediaType`s, e.g. "ANIME".
+    media_type: Option<String>,
+    /// Comma separated Anilist `MediaFormat`s, e.g. "TV,MOVIE".
+    format: Option<String>,
+    /// Comma separated Anilist `MediaStatus`es, e.g. "NOT_YET_RELEASED,RELEASING".
+    status: Option<String>,
+}
+
+/// Character feeds only have the options every media feed has.
+pub type CharacterFeedOptions = MediaFeedOptions;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct StudioFeedOptions {
+    #[serde(flatten)]
+    media: MediaFeedOptions,
+    /// Leaves out media the studio only produced or otherwise supported.
+    /// Defaults to false.
+    main_studio_only: Option<bool>,
+}
+
+/// Reads a bool given as one, e.g. in bundle JSON, or as a query string value.
+/// `#[serde(flatten)]` buffers query string values as strings, which serde
+/// won't turn into a bool on its own.
+fn deserialize_query_bool<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<bool>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum QueryBool {
+        Bool(bool),
+        Text(String),
+    }
+
+    match Option::<QueryBool>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(QueryBool::Bool(value)) => Ok(Some(value)),
+        Some(QueryBool::Text(value)) => value.parse().map(Some).map_err(de::Error::custom),
+    }
+}
+
+impl MediaFeedOptions {
+    /// Parsing the filter is what checks the options, so routes do it once,
+    /// before anything is fetched, and hand the filter to the feed builders.
+    pub fn media_filter(&self) -> Result<MediaFilter, ServiceError> {
+        MediaFilter::new(
+            self.media_type.as_deref(),
+            self.format.as_deref(),
+            self.status.as_deref(),
+        )
+    }
+}
+
+impl StudioFeedOptions {
+    pub fn media_filter(&self) -> Result<MediaFilter, ServiceError> {
+        self.media.media_filter()
+    }
+}
+
+/// Filters parsed from `StaffFeedOptions`, see `MediaFeedOptions::media_filter`.
+pub struct StaffFeedFilters {
+    role: RoleFilter,
+    media: MediaFilter,
+}
+
+impl StaffFeedFilters {
+    /// Whether a credit with this role, or character role, on `m` makes it
+    /// into the feed.
+    fn keeps(&self, role: &str, m: &Media) -> bool {
+        self.role.matches(role)
+            && self.media.matches(
+                m.media_type.as_deref(),
+                m.format.as_deref(),
+                m.status.as_deref(),
+            )
+    }
+}
+
+impl StaffFeedOptions {
+    pub fn filters(&self) -> Result<StaffFeedFilters, ServiceError> {
+        Ok(StaffFeedFilters {
+            role: RoleFilter::new(
+                self.include_role.as_deref(),
+                self.exclude_role.as_deref(),
+                self.include_role_regex.as_deref(),
+                self.exclude_role_regex.as_deref(),
+            )?,
+            media: self.media.media_filter()?,
+        })
+    }
+}
+
+/// Fetches every page of a staff's media from Anilist.
+pub async fn fetch_staff_filmography(
+    client: &AnilistClient,
+    id: i64,
+    cache_mode: CacheMode,
+) -> Result<StaffFilmography, ServiceError> {
+    let (first_page_fetched_at, first_page) =
+        get_staff_media_page(client, id, 1, cache_mode).await?;
+    let staff = first_page.parent;
+    let anilist_staff_name = staff
+        .name
+        .ok_or(errors::anilist_data_format("Staff.name is None"))?;
+
+    let zipped_role_media = fetch_remaining_pages(
+        first_page
+            .entries
+            .into_iter()
+            .map(|(r, m)| (first_page_fetched_at, r, m))
+            .collect(),
+        first_page.last_page,
+        STAFF_MEDIA_BATCH_SIZE,
+        |page| async move {
+            let (fetched_at, page) = get_staff_media_page(client, id, page, cache_mode).await?;
+            Ok(page
+                .entries
+                .into_iter()
+                .map(|(r, m)| (fetched_at, r, m))
+                .collect())
+        },
+    )
+    .await?;
+    // Pages can come from the cache at different times, so the filmography is
+    // only as recent as its oldest page.
+    let fetched_at = zipped_role_media
+        .iter()
+        .map(|(fetched_at, _, _)| *fetched_at)
+        .fold(first_page_fetched_at, DateTime::min);
+
+    let credits = zipped_role_media
+        .into_iter()
+        .filter_map(|(_, r, m)| Some((r?, m?)))
+        .map(|(r, m)| {
+            let role = r
+                .staff_role
+                .ok_or(errors::anilist_data_format("Staff.staffMedia.edges"))?;
+            Ok(StaffCredit {
+                role,
+                media: to_media(m),
+                first_seen_at: None,
+            })
+        })
+        .collect::<Result<Vec<StaffCredit>, ServiceError>>()?;
+
+    Ok(StaffFilmography {
+        staff: Staff {
+            id,
+            name_full: anilist_staff_name.full,
+            name_native: anilist_staff_name.native,
+            site_url: staff.site_url,
+            description: staff.description,
+            image_large: staff.image.and_then(|i| i.large),
+        },
+        credits,
+        first_fetched_at: None,
+        fetched_at: Some(fetched_at),
+        media_changes: Vec::new(),
+    })
+}
+
+/// Fetches every page of the characters a staff voiced from Anilist, one credit
+/// per character and media.
+pub async fn fetch_staff_character_credits(
+    client: &AnilistClient,
+    id: i64,
+    cache_mode: CacheMode,
+) -> Result<Vec<CharacterCredit>, ServiceError> {
+    let first_page = get_character_media_page(client, id, 1, cache_mode).await?;
+    let zipped_character_media = fetch_remaining_pages(
+        first_page.entries,
+        first_page.last_page,
+        CHARACTER_MEDIA_BATCH_SIZE,
+        |page| async move {
+            Ok(get_character_media_page(client, id, page, cache_mode)
+                .await?
+                .entries)
+        },
+    )
+    .await?;
+
+    Ok(zipped_character_media
+        .into_iter()
+        .filter_map(|(e, m)| Some((e?, m?)))
+        .flat_map(|(e, m)| {
+            let media = to_character_media(m);
+            let character_role = e.character_role.as_ref().and_then(enum_name);
+            e.characters
+                .unwrap_or_default()
+                .into_iter()
+                .flatten()
+                .map(move |c| {
+                    let (name_full, name_native) = match c.name {
+                        Some(n) => (n.full, n.native),
+                        None => (None, None),
+                    };
+                    CharacterCredit {
+                        character: Character {
+                            id: c.id,
+                            name_full,
+                            name_native,
+                            image_large: c.image.and_then(|i| i.large),
+                            site_url: c.site_url,
+                        },
+                        character_role: character_role.clone(),
+                        media: media.clone(),
+                        first_seen_at: None,
+                    }
+                })
+        })
+        .collect())
+}
+
+/// Fetches every page of the media a character appears in from Anilist.
+pub async fn fetch_character_appearances(
+    client: &AnilistClient,
+    id: i64,
+    cache_mode: CacheMode,
+) -> Result<CharacterAppearances, ServiceError> {
+    let first_page = get_appearance_page(client, id, 1, cache_mode).await?;
+    let character = first_page.parent;
+    let zipped_appearances = fetch_remaining_pages(
+        first_page.entries,
+        first_page.last_page,
+        CHARACTER_MEDIA_BATCH_SIZE,
+        |page| async move {
+            Ok(get_appearance_page(client, id, page, cache_mode)
+                .await?
+                .entries)
+        },
+    )
+    .await?;
+
+    let appearances = zipped_appearances
+        .into_iter()
+        .filter_map(|(e, m)| Some((e?, m?)))
+        .map(|(e, m)| CharacterAppearance {
+            character_role: e.character_role.as_ref().and_then(enum_name),
+            voice_actors: e
+                .voice_actors
+                .unwrap_or_default()
+                .into_iter()
+                .flatten()
+                .map(|va| {
+                    let (name_full, name_native) = match va.name {
+                        Some(n) => (n.full, n.native),
+                        None => (None, None),
+                    };
+                    VoiceActor {
+                        id: va.id,
+                        name_full,
+                        name_native,
+                        language: va.language_v2,
+                    }
+                })
+                .collect(),
+            media: to_appearance_media(m),
+            first_seen_at: None,
+        })
+        .collect();
+
+    let (name_full, name_native) = match character.name {
+        Some(n) => (n.full, n.native),
+        None => (None, None),
+    };
+    Ok(CharacterAppearances {
+        character: Character {
+            id,
+            name_full,
+            name_native,
+            image_large: character.image.and_then(|i| i.large),
+            site_url: character.site_url,
+        },
+        description: character.description,
+        appearances,
+    })
+}
+
+/// Fetches the latest episodes of the given media that aired in the last
+/// `AIRED_EPISODES_WINDOW_DAYS`, at most `MAX_AIRED_EPISODES_PER_MEDIA` each,
+/// newest first.
+pub async fn fetch_aired_episodes(
+    client: &AnilistClient,
+    media_ids: &[i64],
+    cache_mode: CacheMode,
+) -> Result<Vec<AiredEpisode>, ServiceError> {
+    // Whole days, so the query and its cache key only change once a day.
+    let aired_after = (Utc::now() - Duration::days(AIRED_EPISODES_WINDOW_DAYS))
+        .date_naive()
+        .and_hms_opt(0, 0, 0)
+        .ok_or(errors::internal_logic_error(
+            "Midnight is always a valid time",
+        ))?
+        .and_utc()
+        .timestamp();
+    let mut aired_episodes: Vec<AiredEpisode> = Vec::new();
+    for chunk in media_ids.chunks(AIRED_EPISODES_BATCH_SIZE as usize) {
+        for page in 1..=MAX_AIRED_EPISODES_PAGES {
+            let episodes_page = client
+                .get_aired_episodes(
+                    chunk,
+                    aired_after,
+                    AIRED_EPISODES_BATCH_SIZE,
+                    page,
+                    cache_mode,
+                )
+                .await?
+                .page
+                .ok_or(errors::anilist_data_format("Page is None"))?;
+            aired_episodes.extend(
+                episodes_page
+                    .airing_schedules
+                    .unwrap_or_default()
+                    .into_iter()
+                    .flatten()
+                    .map(to_aired_episode)
+                    .collect::<Result<Vec<AiredEpisode>, ServiceError>>()?,
+            );
+            let has_next_page = episodes_page
+                .page_info
+                .and_then(|p| p.has_next_page)
+                .unwrap_or(false);
+            if !has_next_page {
+                break;
+            }
+        }
+    }
+    Ok(latest_episodes_per_media(aired_episodes))
+}
+
+/// Keeps the first `MAX_AIRED_EPISODES_PER_MEDIA` episodes of each media from
+/// a newest first list.
+fn latest_episodes_per_media(aired_episodes: Vec<AiredEpisode>) -> Vec<AiredEpisode> {
+    let mut kept_per_media: HashMap<i64, usize> = HashMap::new();
+    aired_episodes
+        .into_iter()
+        .filter(|e| {
+            let kept = kept_per_media.entry(e.media_id).or_default();
+            *kept += 1;
+            *kept <= MAX_AIRED_EPISODES_PER_MEDIA
+        })
+        .collect()
+}
+
+fn to_aired_episode(
+    schedule: aired_episodes_query::AiredEpisodesQueryPageAiringSchedules,
+) -> Result<AiredEpisode, ServiceError> {
+    Ok(AiredEpisode {
+        media_id: schedule.media_id,
+        episode: schedule.episode,
+        aired_at: DateTime::from_timestamp(schedule.airing_at, 0).ok_or(
+            errors::anilist_data_format("AiringSchedule.airingAt is not a valid timestamp"),
+        )?,
+    })
+}
+
+/// Fetches every page of a studio's media from Anilist, paging the same way as
+/// `fetch_staff_filmography`.
+pub async fn fetch_studio_filmography(
+    client: &AnilistClient,
+    id: i64,
+    cache_mode: CacheMode,
+) -> Result<StudioFilmography, ServiceError> {
+    let first_page = get_studio_media_page(client, id, 1, cache_mode).await?;
+    let studio = first_page.parent;
+    let zipped_studio_media = fetch_remaining_pages(
+        first_page.entries,
+        first_page.last_page,
+        STUDIO_MEDIA_BATCH_SIZE,
+        |page| async move {
+            Ok(get_studio_media_page(client, id, page, cache_mode)
+                .await?
+                .entries)
+        },
+    )
+    .await?;
+
+    let credits = zipped_studio_media
+        .into_iter()
+        .filter_map(|(e, m)| Some((e?, m?)))
+        .map(|(e, m)| StudioCredit {
+            is_main: e.is_main_studio,
+            media: to_studio_media(m),
+            first_seen_at: None,
+        })
+        .collect();
+
+    Ok(StudioFilmography {
+        studio: Studio {
+            id,
+            name: studio.name,
+            site_url: studio.site_url,
+            is_animation_studio: studio.is_animation_studio,
+        },
+        credits,
+    })
+}
+
+/// Fetches pages 2 through `last_page` a few at a time, then keeps going while
+/// pages come back full since Anilist's pageInfo is only an estimate. Returns
+/// the entries of every page, `first_page` included, in order.
+async fn fetch_remaining_pages<T, F, Fut>(
+    first_page: Vec<T>,
+    last_page: i64,
+    batch_size: i64,
+    get_page: F,
+) -> Result<Vec<T>, ServiceError>
+where
+    F: Fn(i64) -> Fut,
+    Fut: Future<Output = Result<Vec<T>, ServiceError>>,
+{
+    let mut pages = vec![first_page];
+    pages.extend(
+        stream::iter(2..=last_page)
+            .map(&get_page)
+            .buffered(STAFF_MEDIA_PAGE_CONCURRENCY)
+            .try_collect::<Vec<Vec<T>>>()
+            .await?,
+    );
+
+    let mut current_page = last_page.max(1) + 1;
+    while pages.last().is_some_and(|p| p.len() == batch_size as usize) {
+        pages.push(get_page(current_page).await?);
+        current_page += 1;
+    }
+    Ok(pages.into_iter().flatten().collect())
+}
+
+/// Parses a comma separated list of staff ids, dropping repeats.
+pub fn parse_staff_ids(ids: &str) -> Result<Vec<i64>, ServiceError> {
+    let staff_ids = ids
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| {
+            id.parse().map_err(|_| {
+                errors::invalid_parameter("ids", "staff ids must be numbers".to_string())
+            })
+        })
+        .collect::<Result<Vec<i64>, ServiceError>>()?;
+    check_staff_ids(staff_ids)
+}
+
+/// Drops repeated staff ids, keeping their first position, and makes sure
+/// what's left is small enough for one combined feed.
+pub fn check_staff_ids(ids: Vec<i64>) -> Result<Vec<i64>, ServiceError> {
+    let mut staff_ids: Vec<i64> = Vec::with_capacity(ids.len());
+    for id in ids {
+        if !staff_ids.contains(&id) {
+            staff_ids.push(id);
+        }
+    }
+    if staff_ids.is_empty() {
+        return Err(errors::invalid_parameter(
+            "ids",
+            "at least one staff id is required".to_string(),
+        ));
+    }
+    if staff_ids.len() > MAX_COMBINED_STAFF {
+        return Err(errors::invalid_parameter(
+            "ids",
+            format!("at most {} staff ids are allowed", MAX_COMBINED_STAFF),
+        ));
+    }
+    Ok(staff_ids)
+}
+
+/// Merges the feeds of several staff into one, newest first. Staff that can't
+/// be fetched are left out so one bad id doesn't break the whole feed; only
+/// when none can be fetched is the first error returned. `link` is the URL of
+/// the combined feed itself and doubles as its Atom id, so it has to differ
+/// between combinations.
+pub async fn build_combined_staff_feed(
+    repository: &AnilistRepository,
+    staff_ids: &[i64],
+    options: &StaffFeedOptions,
+    filters: &StaffFeedFilters,
+    link: String,
+) -> Result<Feed, ServiceError> {
+    let results: Vec<(i64, Result<Feed, ServiceError>)> = stream::iter(staff_ids.iter().copied())
+        .map(|id| async move { (id, load_staff_feed(repository, id, options, filters).await) })
+        .buffered(COMBINED_STAFF_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut feeds: Vec<Feed> = Vec::with_capacity(results.len());
+    let mut first_error: Option<ServiceError> = None;
+    for (id, result) in results {
+        match result {
+            Ok(feed) => feeds.push(feed),
+            Err(e) => {
+                warn!("Leaving staff {} out of a combined feed: {}", id, e);
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    match first_error {
+        Some(e) if feeds.is_empty() => Err(e),
+        _ => Ok(combine_feeds(feeds, link)),
+    }
+}
+
+fn combine_feeds(feeds: Vec<Feed>, link: String) -> Feed {
+    let names: Vec<String> = feeds.iter().map(|f| f.title.clone()).collect();
+    let mut title = names
+        .iter()
+        .take(COMBINED_TITLE_NAMES)
+        .cloned()
+        .collect::<Vec<String>>()
+        .join(" / ");
+    if names.len() > COMBINED_TITLE_NAMES {
+        title += &format!(" and {} more", names.len() - COMBINED_TITLE_NAMES);
+    }
+
+    let mut guids: HashSet<String> = HashSet::new();
+    let mut items: Vec<FeedItem> = feeds
+        .into_iter()
+        .flat_map(|f| f.items)
+        .filter(|i| guids.insert(i.guid.clone()))
+        .collect();
+    items.sort_by_key(|i| Reverse(i.published));
+
+    Feed {
+        title,
+        link,
+        description: format!("Anilist credits of {}.", names.join(" / ")),
+        image_url: ANILIST_ICON_URL.to_string(),
+        items,
+    }
+}
+
+/// Number of staffMedia pages, and so Anilist requests, a fetch of a staff with
+/// `credit_count` credits takes.
+pub fn staff_media_page_count(credit_count: usize) -> u32 {
+    (credit_count as u32 / STAFF_MEDIA_BATCH_SIZE as u32) + 1
+}
+
+/// Loads the staff along with their character roles when `options` asks for
+/// them, and builds their feed.
+pub async fn load_staff_feed(
+    repository: &AnilistRepository,
+    id: i64,
+    options: &StaffFeedOptions,
+    filters: &StaffFeedFilters,
+) -> Result<Feed, ServiceError> {
+    let filmography = repository.staff_filmography(id).await?;
+    let credit_kinds = options.credits.unwrap_or_default();
+    let character_credits = if credit_kinds.includes_characters() {
+        repository.staff_character_credits(id).await?
+    } else {
+        Vec::new()
+    };
+    let aired_episodes = if options.episodes.unwrap_or(false) {
+        // Only media that make it into the feed are worth asking Anilist about.
+        let production_credits = if credit_kinds.includes_production() {
+            filmography.credits.as_slice()
+        } else {
+            &[]
+        };
+        let mut media_ids: Vec<i64> = production_credits
+            .iter()
+            .filter(|c| filters.keeps(&c.role, &c.media))
+            .map(|c| &c.media)
+            .chain(
+                character_credits
+                    .iter()
+                    .filter(|c| {
+                        filters.keeps(&character_role_name(c.character_role.as_deref()), &c.media)
+                    })
+                    .map(|c| &c.media),
+            )
+            .filter(|m| airs_episodes(m))
+            .map(|m| m.id)
+            .collect();
+        media_ids.sort_unstable();
+        media_ids.dedup();
+        if media_ids.is_empty() {
+            Vec::new()
+        } else {
+            repository.aired_episodes(&media_ids).await?
+        }
+    } else {
+        Vec::new()
+    };
+    build_staff_feed(
+        filmography,
+        character_credits,
+        aired_episodes,
+        options,
+        filters,
+    )
+}
+
+/// Turns each of the staff's media, or each role/media pair, into a feed item,
+/// along with an item for every status or start date change and one for every
+/// character voiced, newest first. Anime with `aired_episodes` get one item per
+/// episode instead.
+pub fn build_staff_feed(
+    filmography: StaffFilmography,
+    character_credits: Vec<CharacterCredit>,
+    aired_episodes: Vec<AiredEpisode>,
+    options: &StaffFeedOptions,
+    filters: &StaffFeedFilters,
+) -> Result<Feed, ServiceError> {
+    let credit_kinds = options.credits.unwrap_or_default();
+    let staff = filmography.staff;
+    let staff_name = join_names(vec![staff.name_full, staff.name_native]);
+
+    let production_credits = if credit_kinds.includes_production() {
+        filmography.credits
+    } else {
+        Vec::new()
+    };
+    let filtered_media: Vec<AnilistMedia> = production_credits
+        .into_iter()
+        .filter(|c| filters.keeps(&c.role, &c.media))
+        .map(|c| AnilistMedia {
+            roles: vec![c.role],
+            media: c.media,
+            first_seen_at: c.first_seen_at,
+        })
+        .collect();
+
+    let mut change_items = if options.include_changes.unwrap_or(true) {
+        media_change_items(
+            staff.id,
+            &staff_name,
+            &filtered_media,
+            &filmography.media_changes,
+        )
+    } else {
+        Vec::new()
+    };
+
+    let one_item_per_role = options.one_item_per_role.unwrap_or(false);
+    let grouped_media = if one_item_per_role {
+        filtered_media
+    } else {
+        group_roles_by_media(filtered_media)
+    };
+
+    let include_undated = options.media.include_undated.unwrap_or(true);
+    let order_by = options.order_by.unwrap_or_default();
+    let first_fetched_at = filmography.first_fetched_at;
+    let mut episodes_by_media_id: HashMap<i64, Vec<AiredEpisode>> = HashMap::new();
+    for episode in aired_episodes {
+        episodes_by_media_id
+            .entry(episode.media_id)
+            .or_default()
+            .push(episode);
+    }
+
+    let mut character_guids: HashSet<String> = HashSet::new();
+    let mut character_items: Vec<FeedItem> = character_credits
+        .into_iter()
+        .filter(|c| filters.keeps(&character_role_name(c.character_role.as_deref()), &c.media))
+        .map(|c| {
+            let episodes = aired_episodes_of(&episodes_by_media_id, &c.media);
+            let start = item_date(&c.media.start_date, c.first_seen_at)?;
+            if start.precision != DatePrecision::Day && !include_undated && episodes.is_none() {
+                return Ok(Vec::new());
+            }
+            let media_description = c.media.description.clone();
+            let item = to_character_feed_item(staff.id, &staff_name, c, start);
+            Ok(match episodes {
+                Some(episodes) => episode_items(&item, media_description.as_deref(), episodes),
+                None => vec![item],
+            })
+        })
+        .collect::<Result<Vec<Vec<FeedItem>>, ServiceError>>()?
+        .into_iter()
+        .flatten()
+        // Anilist lists a character once per dub a staff is credited for.
+        .filter(|i| character_guids.insert(i.guid.clone()))
+        .collect();
+    let mut items: Vec<FeedItem> = grouped_media
+        .into_iter()
+        .map(|anilist_media| {
+            let episodes = aired_episodes_of(&episodes_by_media_id, &anilist_media.media);
+            let start = item_date(&anilist_media.media.start_date, anilist_media.first_seen_at)?;
+            if start.precision != DatePrecision::Day && !include_undated && episodes.is_none() {
+                return Ok(Vec::new());
+            }
+            let start = match order_by {
+                FeedOrder::StartDate => start,
+                FeedOrder::Discovered => {
+                    discovered_date(anilist_media.first_seen_at, first_fetched_at).unwrap_or(start)
+                }
+            };
+            let media_description = anilist_media.media.description.clone();
+            let item = to_feed_item(
+                staff.id,
+                &staff_name,
+                anilist_media,
+                start,
+                one_item_per_role,
+            )?;
+            Ok(match episodes {
+                Some(episodes) => episode_items(&item, media_description.as_deref(), episodes),
+                None => vec![item],
+            })
+        })
+        .collect::<Result<Vec<Vec<FeedItem>>, ServiceError>>()?
+        .into_iter()
+        .flatten()
+        .collect();
+
+    items.append(&mut change_items);
+    items.append(&mut character_items);
+    items.sort_by_key(|i| Reverse(i.published));
+
+    let site_url = staff
+        .site_url
+        .ok_or(errors::anilist_data_format("Staff.siteUrl is None"))?;
+    let image_url = staff
+        .image_large
+        .ok_or(errors::anilist_data_format("Staff.image.large is None"))?;
+    let description = staff
+        .description
+        .unwrap_or_else(|| NO_STAFF_DESCRIPTION.to_string());
+
+    Ok(Feed {
+        title: staff_name,
+        link: site_url,
+        description,
+        image_url,
+        items,
+    })
+}
+
+/// Loads the studio and builds its feed.
+pub async fn load_studio_feed(
+    repository: &AnilistRepository,
+    id: i64,
+    options: &StudioFeedOptions,
+    media_filter: &MediaFilter,
+) -> Result<Feed, ServiceError> {
+    build_studio_feed(
+        repository.studio_filmography(id).await?,
+        options,
+        media_filter,
+    )
+}
+
+/// One item per media the studio worked on, newest first. Dates are resolved
+/// the same way as in staff feeds.
+pub fn build_studio_feed(
+    filmography: StudioFilmography,
+    options: &StudioFeedOptions,
+    media_filter: &MediaFilter,
+) -> Result<Feed, ServiceError> {
+    let studio = filmography.studio;
+    let main_studio_only = options.main_studio_only.unwrap_or(false);
+    let credits = filmography
+        .credits
+        .into_iter()
+        .filter(|c| c.is_main || !main_studio_only);
+    let items = media_credit_items(credits, &options.media, media_filter, |c, start| {
+        to_studio_feed_item(studio.id, &studio.name, c, start)
+    })?;
+
+    let site_url = studio
+        .site_url
+        .ok_or(errors::anilist_data_format("Studio.siteUrl is None"))?;
+    let kind = if studio.is_animation_studio {
+        "animation studio"
+    } else {
+        "studio"
+    };
+
+    Ok(Feed {
+        description: format!("Anilist media of the {} {}.", kind, studio.name),
+        title: studio.name,
+        link: site_url,
+        image_url: ANILIST_ICON_URL.to_string(),
+        items,
+    })
+}
+
+/// Loads the character and builds their feed.
+pub async fn load_character_feed(
+    repository: &AnilistRepository,
+    id: i64,
+    options: &CharacterFeedOptions,
+    media_filter: &MediaFilter,
+) -> Result<Feed, ServiceError> {
+    build_character_feed(
+        repository.character_appearances(id).await?,
+        options,
+        media_filter,
+    )
+}
+
+/// One item per media the character appears in, newest first, naming who
+/// voiced them there.
+pub fn build_character_feed(
+    appearances: CharacterAppearances,
+    options: &CharacterFeedOptions,
+    media_filter: &MediaFilter,
+) -> Result<Feed, ServiceError> {
+    let character = appearances.character;
+    let character_name = join_names(vec![character.name_full, character.name_native]);
+    let items = media_credit_items(
+        appearances.appearances,
+        options,
+        media_filter,
+        |a, start| to_appearance_feed_item(character.id, &character_name, a, start),
+    )?;
+
+    let site_url = character
+        .site_url
+        .ok_or(errors::anilist_data_format("Character.siteUrl is None"))?;
+
+    Ok(Feed {
+        title: character_name,
+        link: site_url,
+        description: appearances
+            .description
+            .unwrap_or_else(|| NO_CHARACTER_DESCRIPTION.to_string()),
+        image_url: character
+            .image_large
+            .unwrap_or_else(|| ANILIST_ICON_URL.to_string()),
+        items,
+    })
+}
+
+/// One item per media that passes the filters, newest first.
+fn media_credit_items<C: MediaCredit>(
+    credits: impl IntoIterator<Item = C>,
+    options: &MediaFeedOptions,
+    media_filter: &MediaFilter,
+    to_item: impl Fn(C, ItemDate) -> FeedItem,
+) -> Result<Vec<FeedItem>, ServiceError> {
+    let include_undated = options.include_undated.unwrap_or(true);
+    // Anilist sometimes repeats a media across pages.
+    let mut media_ids: HashSet<i64> = HashSet::new();
+
+    let mut items: Vec<FeedItem> = credits
+        .into_iter()
+        .filter(|c| {
+            let m = c.media();
+            media_filter.matches(
+                m.media_type.as_deref(),
+                m.format.as_deref(),
+                m.status.as_deref(),
+            )
+        })
+        .filter(|c| media_ids.insert(c.media().id))
+        .map(|c| {
+            let start = item_date(&c.media().start_date, c.first_seen_at())?;
+            if start.precision != DatePrecision::Day && !include_undated {
+                return Ok(None);
+            }
+            Ok(Some(to_item(c, start)))
+        })
+        .collect::<Result<Vec<Option<FeedItem>>, ServiceError>>()?
+        .into_iter()
+        .flatten()
+        .collect();
+    items.sort_by_key(|i| Reverse(i.published));
+    Ok(items)
+}
+
+/// Takes the connection `$field` out of `$parent` and pairs up its edges and
+/// nodes. graphql_client generates separate types for every query, so this
+/// can't be a function.
+macro_rules! connection_page {
+    ($parent:expr, $field:ident, $path:literal) => {{
+        let mut parent = $parent;
+        let connection = parent
+            .$field
+            .take()
+            .ok_or(errors::anilist_data_format(concat!($path, " is None")))?;
+        let last_page = connection
+            .page_info
+            .as_ref()
+            .and_then(|p| p.last_page)
+            .unwrap_or(1);
+        let edges = connection.edges.ok_or(errors::anilist_data_format(concat!(
+            $path,
+            ".edges is None"
+        )))?;
+        let nodes = connection.nodes.ok_or(errors::anilist_data_format(concat!(
+            $path,
+            ".nodes is None"
+        )))?;
+        ConnectionPage {
+            parent,
+            last_page,
+            entries: edges.into_iter().zip(nodes).collect(),
+        }
+    }};
+}
+
+/// A page of the staff's media along with when Anilist sent it.
+async fn get_staff_media_page(
+    client: &AnilistClient,
+    id: i64,
+    page: i64,
+    cache_mode: CacheMode,
+) -> Result<
+    (
+        DateTime<Utc>,
+        ConnectionPage<StaffMediaQueryStaff, RoleMedia>,
+    ),
+    ServiceError,
+> {
+    let fetched = client
+        .get_staff_media(id, STAFF_MEDIA_BATCH_SIZE, page, cache_mode)
+        .await?;
+    let staff = fetched
+        .data
+        .staff
+        .ok_or(errors::anilist_data_format(STAFF_NONE))?;
+    Ok((
+        fetched.fetched_at,
+        connection_page!(staff, staff_media, "Staff.staffMedia"),
+    ))
+}
+
+async fn get_character_media_page(
+    client: &AnilistClient,
+    id: i64,
+    page: i64,
+    cache_mode: CacheMode,
+) -> Result<ConnectionPage<StaffCharacterMediaQueryStaff, CharacterMedia>, ServiceError> {
+    let staff = client
+        .get_staff_character_media(id, CHARACTER_MEDIA_BATCH_SIZE, page, cache_mode)
+        .await?
+        .staff
+        .ok_or(errors::anilist_data_format(STAFF_NONE))?;
+    Ok(connection_page!(
+        staff,
+        character_media,
+        "Staff.characterMedia"
+    ))
+}
+
+async fn get_appearance_page(
+    client: &AnilistClient,
+    id: i64,
+    page: i64,
+    cache_mode: CacheMode,
+) -> Result<ConnectionPage<CharacterMediaQueryCharacter, Appearance>, ServiceError> {
+    let character = client
+        .get_character_media(id, CHARACTER_MEDIA_BATCH_SIZE, page, cache_mode)
+        .await?
+        .character
+        .ok_or(errors::anilist_data_format(CHARACTER_NONE))?;
+    Ok(connection_page!(character, media, "Character.media"))
+}
+
+async fn get_studio_media_page(
+    client: &AnilistClient,
+    id: i64,
+    page: i64,
+    cache_mode: CacheMode,
+) -> Result<ConnectionPage<StudioMediaQueryStudio, StudioMedia>, ServiceError> {
+    let studio = client
+        .get_studio_media(id, STUDIO_MEDIA_BATCH_SIZE, page, cache_mode)
+        .await?
+        .studio
+        .ok_or(errors::anilist_data_format(STUDIO_NONE))?;
+    Ok(connection_page!(studio, media, "Studio.media"))
+}
+
+/// graphql_client generates a separate media node type for every query, all
+/// with the fields selected in the staff media query.
+macro_rules! to_media {
+    ($m:expr) => {{
+        let m = $m;
+        let (title_romaji, title_english, title_native) = match m.title {
+            Some(t) => (t.romaji, t.english, t.native),
+            None => (None, None, None),
+        };
+        let start_date = match m.start_date {
+            Some(d) => FuzzyDate {
+                year: d.year,
+                month: d.month,
+                day: d.day,
+            },
+            None => FuzzyDate::default(),
+        };
+
+        Media {
+            id: m.id,
+            title_romaji,
+            title_english,
+            title_native,
+            media_type: m.type_.as_ref().and_then(enum_name),
+            format: m.format.as_ref().and_then(enum_name),
+            status: m.status.as_ref().and_then(enum_name),
+            description: m.description,
+            site_url: m.site_url,
+            start_date,
+        }
+    }};
+}
+
+fn to_media(m: StaffMediaQueryStaffStaffMediaNodes) -> Media {
+    to_media!(m)
+}
+
+fn to_character_media(m: StaffCharacterMediaQueryStaffCharacterMediaNodes) -> Media {
+    to_media!(m)
+}
+
+fn to_appearance_media(m: CharacterMediaQueryCharacterMediaNodes) -> Media {
+    to_media!(m)
+}
+
+fn to_studio_media(m: StudioMediaQueryStudioMediaNodes) -> Media {
+    to_media!(m)
+}
+
+fn to_feed_item(
+    staff_id: i64,
+    staff_name: &str,
+    anilist_media: AnilistMedia,
+    start: ItemDate,
+    one_item_per_role: bool,
+) -> Result<FeedItem, ServiceError> {
+    let m = anilist_media.media;
+    let roles = anilist_media.roles;
+    let guid = match (one_item_per_role, roles.first()) {
+        (true, Some(role)) => staff_media_guid(staff_id, m.id, Some(role)),
+        _ => staff_media_guid(staff_id, m.id, None),
+    };
+    let title = match media_title(&m) {
+        Some(media_title) => {
+            let title_roles = if one_item_per_role {
+                roles.join(", ")
+            } else {
+                title_roles(&roles)
+            };
+            staff_name.to_string() + " as " + &title_roles + " on " + &media_title
+        }
+        None => NO_MEDIA_TITLE.to_string(),
+    };
+
+    let mut notes = String::new();
+    if roles.len() > 1 {
+        notes += &format!("<p>Roles: {}</p>", escape_html(&roles.join(", ")));
+    }
+    if let Some(note) = approximate_date_note(&start) {
+        notes += &note;
+    }
+    let description = if notes.is_empty() {
+        m.description
+    } else {
+        Some(notes + &m.description.unwrap_or_default())
+    };
+
+    Ok(FeedItem {
+        guid,
+        title,
+        link: m.site_url,
+        description,
+        published: start.published,
+        date_precision: start.precision,
+        role: Some(roles.join(", ")),
+        media_type: m.media_type,
+        format: m.format,
+        status: m.status,
+        first_seen_at: anilist_media.first_seen_at,
+        image_url: None,
+    })
+}
+
+/// Anime that are airing or yet to air, whose items can be split into episodes.
+fn airs_episodes(m: &Media) -> bool {
+    m.media_type.as_deref() == Some("ANIME")
+        && matches!(m.status.as_deref(), Some("RELEASING" | "NOT_YET_RELEASED"))
+}
+
+/// Episodes to split the media's item into, `None` when it should stay a
+/// single item.
+fn aired_episodes_of<'a>(
+    episodes_by_media_id: &'a HashMap<i64, Vec<AiredEpisode>>,
+    m: &Media,
+) -> Option<&'a [AiredEpisode]> {
+    if !airs_episodes(m) {
+        return None;
+    }
+    episodes_by_media_id
+        .get(&m.id)
+        .map(Vec::as_slice)
+        .filter(|e| !e.is_empty())
+}
+
+/// One item per aired episode in place of `series`, e.g. "X as Director on
+/// Title, episode 3", dated by when the episode aired. GUIDs extend the
+/// series GUID with the episode number.
+fn episode_items(
+    series: &FeedItem,
+    media_description: Option<&str>,
+    episodes: &[AiredEpisode],
+) -> Vec<FeedItem> {
+    episodes
+        .iter()
+        .map(|e| FeedItem {
+            guid: format!("{}:episode:{}", series.guid, e.episode),
+            title: format!("{}, episode {}", series.title, e.episode),
+            link: series.link.clone(),
+            description: Some(
+                format!(
+                    "<p><i>Episode {} aired on {}.</i></p>",
+                    e.episode,
+                    e.aired_at.format("%B %-d, %Y at %H:%M UTC")
+                ) + media_description.unwrap_or_default(),
+            ),
+            published: e.aired_at,
+            date_precision: DatePrecision::Day,
+            role: series.role.clone(),
+            media_type: series.media_type.clone(),
+            format: series.format.clone(),
+            status: series.status.clone(),
+            first_seen_at: series.first_seen_at,
+            image_url: series.image_url.clone(),
+        })
+        .collect()
+}
+
+/// "X as Character (Main) in Title", with the character's picture.
+fn to_character_feed_item(
+    staff_id: i64,
+    staff_name: &str,
+    credit: CharacterCredit,
+    start: ItemDate,
+) -> FeedItem {
+    let m = credit.media;
+    let c = credit.character;
+    let character_name = c
+        .name_full
+        .or(c.name_native)
+        .unwrap_or_else(|| NO_CHARACTER_NAME.to_string());
+    let character = match credit
+        .character_role
+        .as_deref()
+        .and_then(character_role_label)
+    {
+        Some(label) => format!("{} ({})", character_name, label),
+        None => character_name,
+    };
+    let title = match media_title(&m) {
+        Some(media_title) => format!("{} as {} in {}", staff_name, character, media_title),
+        None => NO_MEDIA_TITLE.to_string(),
+    };
+
+    let mut notes = String::new();
+    let image = c
+        .image_large
+        .as_ref()
+        .map(|url| format!("<img src=\"{}\">", escape_html(url)));
+    match (image, &c.site_url) {
+        (Some(image), Some(site_url)) => {
+            notes += &format!("<p><a href=\"{}\">{}</a></p>", escape_html(site_url), image)
+        }
+        (Some(image), None) => notes += &format!("<p>{}</p>", image),
+        (None, _) => {}
+    }
+    if let Some(note) = approximate_date_note(&start) {
+        notes += &note;
+    }
+
+    FeedItem {
+        guid: format!(
+            "{}:character:{}",
+            staff_media_guid(staff_id, m.id, None),
+            c.id
+        ),
+        title,
+        link: m.site_url,
+        description: Some(notes + &m.description.unwrap_or_default()),
+        published: start.published,
+        date_precision: start.precision,
+        role: Some(character_role_name(credit.character_role.as_deref())),
+        media_type: m.media_type,
+        format: m.format,
+        status: m.status,
+        first_seen_at: None,
+        image_url: c.image_large,
+    }
+}
+
+/// "Character (Main) in Title", listing the voice actors in the description.
+fn to_appearance_feed_item(
+    character_id: i64,
+    character_name: &str,
+    appearance: CharacterAppearance,
+    start: ItemDate,
+) -> FeedItem {
+    let m = appearance.media;
+    let character = match appearance
+        .character_role
+        .as_deref()
+        .and_then(character_role_label)
+    {
+        Some(label) => format!("{} ({})", character_name, label),
+        None => character_name.to_string(),
+    };
+    let title = match media_title(&m) {
+        Some(media_title) => format!("{} in {}", character, media_title),
+        None => NO_MEDIA_TITLE.to_string(),
+    };
+
+    let mut notes = String::new();
+    if !appearance.voice_actors.is_empty() {
+        let voice_actors: Vec<String> = appearance
+            .voice_actors
+            .iter()
+            .map(|va| {
+                let name = join_names(vec![va.name_full.clone(), va.name_native.clone()]);
+                let link = format!(
+                    "<a href=\"https://anilist.co/staff/{}\">{}</a>",
+                    va.id,
+                    escape_html(&name)
+                );
+                match &va.language {
+                    Some(language) => format!("{} ({})", link, escape_html(language)),
+                    None => link,
+                }
+            })
+            .collect();
+        notes += &format!("<p>Voiced by {}.</p>", voice_actors.join(", "));
+    }
+    if let Some(note) = approximate_date_note(&start) {
+        notes += &note;
+    }
+    let description = if notes.is_empty() {
+        m.description
+    } else {
+        Some(notes + &m.description.unwrap_or_default())
+    };
+
+    FeedItem {
+        guid: format!(
+            "urn:creator-follower:anilist:character:{}:media:{}",
+            character_id, m.id
+        ),
+        title,
+        link: m.site_url,
+        description,
+        published: start.published,
+        date_precision: start.precision,
+        role: Some(character_role_name(appearance.character_role.as_deref())),
+        media_type: m.media_type,
+        format: m.format,
+        status: m.status,
+        first_seen_at: None,
+        image_url: None,
+    }
+}
+
+/// Anilist `CharacterRole` as shown in item titles.
+fn character_role_label(character_role: &str) -> Option<&'static str> {
+    match character_role {
+        "MAIN" => Some("Main"),
+        "SUPPORTING" => Some("Supporting"),
+        "BACKGROUND" => Some("Background"),
+        _ => None,
+    }
+}
+
+/// Role of a character credit, e.g. "Main character", which role filters match
+/// against like any production role.
+fn character_role_name(character_role: Option<&str>) -> String {
+    match character_role.and_then(character_role_label) {
+        Some(label) => format!("{} character", label),
+        None => "Character".to_string(),
+    }
+}
+
+fn to_studio_feed_item(
+    studio_id: i64,
+    studio_name: &str,
+    credit: StudioCredit,
+    start: ItemDate,
+) -> FeedItem {
+    let m = credit.media;
+    let title = match media_title(&m) {
+        Some(media_title) => media_title + " by " + studio_name,
+        None => NO_MEDIA_TITLE.to_string(),
+    };
+    let description = match approximate_date_note(&start) {
+        Some(note) => Some(note + &m.description.unwrap_or_default()),
+        None => m.description,
+    };
+    let role = if credit.is_main {
+        "Main studio"
+    } else {
+        "Studio"
+    };
+
+    FeedItem {
+        guid: format!(
+            "urn:creator-follower:anilist:studio:{}:media:{}",
+            studio_id, m.id
+        ),
+        title,
+        link: m.site_url,
+        description,
+        published: start.published,
+        date_precision: start.precision,
+        role: Some(role.to_string()),
+        media_type: m.media_type,
+        format: m.format,
+        status: m.status,
+        first_seen_at: None,
+        image_url: None,
+    }
+}
+
+/// One item per change to the media in `credits`, e.g. "Title (X as Director)
+/// started airing", dated by when the change was seen. Roles are always merged
+/// so a change shows up once per media.
+fn media_change_items(
+    staff_id: i64,
+    staff_name: &str,
+    credits: &[AnilistMedia],
+    changes: &[MediaChange],
+) -> Vec<FeedItem> {
+    let mut roles_by_media_id: HashMap<i64, (&Media, Vec<String>)> = HashMap::new();
+    for credit in credits {
+        let (_, roles) = roles_by_media_id
+            .entry(credit.media.id)
+            .or_insert_with(|| (&credit.media, Vec::new()));
+        for role in &credit.roles {
+            if !roles.contains(role) {
+                roles.push(role.clone());
+            }
+        }
+    }
+
+    changes
+        .iter()
+        .filter_map(|change| {
+            let (m, roles) = roles_by_media_id.get(&change.media_id)?;
+            let event = media_change_event(change, m.media_type.as_deref());
+            let title = match media_title(m) {
+                Some(media_title) => format!(
+                    "{} ({} as {}) {}",
+                    media_title,
+                    staff_name,
+                    title_roles(roles),
+                    event
+                ),
+                None => format!("{} {}", NO_MEDIA_TITLE, event),
+            };
+            let description = format!(
+                "<p>{}: {} \u{2192} {}.</p>",
+                match change.kind {
+                    MediaChangeKind::Status => "Status",
+                    MediaChangeKind::StartDate => "Start date",
+                },
+                change.old_value.as_deref().unwrap_or("none"),
+                change.new_value.as_deref().unwrap_or("none"),
+            );
+
+            Some(FeedItem {
+                guid: format!(
+                    "{}:change:{}",
+                    staff_media_guid(staff_id, m.id, None),
+                    change.id
+                ),
+                title,
+                link: m.site_url.clone(),
+                description: Some(description + m.description.as_deref().unwrap_or_default()),
+                published: change.changed_at,
+                date_precision: DatePrecision::Discovered,
+                role: Some(roles.join(", ")),
+                media_type: m.media_type.clone(),
+                format: m.format.clone(),
+                status: m.status.clone(),
+                first_seen_at: None,
+                image_url: None,
+            })
+        })
+        .collect()
+}
+
+/// What happened to the media, phrased to follow its title.
+fn media_change_event(change: &MediaChange, media_type: Option<&str>) -> String {
+    let new_value = change.new_value.as_deref();
+    let releasing = match media_type {
+        Some("MANGA") => "publishing",
+        _ => "airing",
+    };
+    match change.kind {
+        MediaChangeKind::Status => match new_value {
+            Some("RELEASING") => format!("started {}", releasing),
+            Some("FINISHED") => format!("finished {}", releasing),
+            Some("CANCELLED") => "was cancelled".to_string(),
+            Some("HIATUS") => "went on hiatus".to_string(),
+            Some("NOT_YET_RELEASED") => "is not yet released".to_string(),
+            Some(status) => format!("status changed to {}", status),
+            None => "status was removed".to_string(),
+        },
+        MediaChangeKind::StartDate => match (change.old_value.as_deref(), new_value) {
+            (_, None) => "start date was removed".to_string(),
+            (Some(old), Some(new)) if !new.starts_with(old) => {
+                format!("start date changed to {}", new)
+            }
+            (_, Some(new)) => format!("start date confirmed for {}", new),
+        },
+    }
+}
+
+/// English or romaji title followed by the native one, `None` when Anilist
+/// has neither.
+fn media_title(m: &Media) -> Option<String> {
+    let english = m.title_english.clone().or_else(|| m.title_romaji.clone());
+    if english.is_none() && m.title_native.is_none() {
+        return None;
+    }
+    Some(join_names(vec![english, m.title_native.clone()]))
+}
+
+/// Folds every role on the same media into the media's first occurrence, keeping
+/// Anilist's ordering of both media and roles.
+fn group_roles_by_media(media: Vec<AnilistMedia>) -> Vec<AnilistMedia> {
+    let mut grouped: Vec<AnilistMedia> = Vec::with_capacity(media.len());
+    let mut index_by_media_id: HashMap<i64, usize> = HashMap::new();
+    for anilist_media in media {
+        match index_by_media_id.get(&anilist_media.media.id) {
+            Some(&i) => {
+                for role in anilist_media.roles {
+                    if !grouped[i].roles.contains(&role) {
+                        grouped[i].roles.push(role);
+                    }
+                }
+                grouped[i].first_seen_at =
+                    match (grouped[i].first_seen_at, anilist_media.first_seen_at) {
+                        (Some(a), Some(b)) => Some(a.min(b)),
+                        (a, b) => a.or(b),
+                    };
+            }
+            None => {
+                index_by_media_id.insert(anilist_media.media.id, grouped.len());
+                grouped.push(anilist_media);
+            }
+        }
+    }
+    grouped
+}
+
+/// Short form of the roles for item titles. Qualifiers such as "(OP)" or
+/// "(ep 1)" are dropped, e.g. "Director, Storyboard".
+fn title_roles(roles: &[String]) -> String {
+    let mut short_roles: Vec<&str> = Vec::with_capacity(roles.len());
+    for role in roles {
+        let short_role = role.split(" (").next().unwrap_or(role).trim();
+        if !short_roles.contains(&short_role) {
+            short_roles.push(short_role);
+        }
+    }
+    short_roles.join(", ")
+}
+
+/// Resolves Anilist's fuzzy start date to a best-effort date. Missing days and
+/// months fall back to the start of the month or year, and media without any
+/// announced year are dated at the time they were first seen, which has to be
+/// known for them so the date doesn't change between builds.
+fn item_date(
+    start_date: &FuzzyDate,
+    first_seen: Option<DateTime<Utc>>,
+) -> Result<ItemDate, ServiceError> {
+    let (naive_date, precision) = match (start_date.year, start_date.month, start_date.day) {
+        (Some(y), Some(m), Some(d)) => (
+            NaiveDate::from_ymd_opt(y as i32, m as u32, d as u32),
+            DatePrecision::Day,
+        ),
+        (Some(y), Some(m), None) => (
+            NaiveDate::from_ymd_opt(y as i32, m as u32, 1),
+            DatePrecision::Month,
+        ),
+        (Some(y), None, _) => (NaiveDate::from_ymd_opt(y as i32, 1, 1), DatePrecision::Year),
+        (None, _, _) => {
+            return Ok(ItemDate {
+                published: first_seen.ok_or(errors::internal_logic_error(
+                    "Media without a start year has no first seen date",
+                ))?,
+                precision: DatePrecision::Unknown,
+            })
+        }
+    };
+    let published = naive_date
+        .ok_or(errors::anilist_data_format(
+            "Media.startDate is not a valid date",
+        ))?
+        .and_hms_opt(0, 0, 0)
+        .ok_or(errors::internal_logic_error(
+            "Midnight is always a valid time",
+        ))?
+        .and_utc();
+
+    Ok(ItemDate {
+        published,
+        precision,
+    })
+}
+
+/// Dates a credit by when it was first seen, as long as that was after the
+/// staff's first fetch and so is a real discovery.
+fn discovered_date(
+    first_seen_at: Option<DateTime<Utc>>,
+    first_fetched_at: Option<DateTime<Utc>>,
+) -> Option<ItemDate> {
+    match (first_seen_at, first_fetched_at) {
+        (Some(seen), Some(fetched)) if seen > fetched => Some(ItemDate {
+            published: seen,
+            precision: DatePrecision::Discovered,
+        }),
+        _ => None,
+    }
+}
+
+/// Html paragraph telling readers that an item's date is a best guess.
+fn approximate_date_note(date: &ItemDate) -> Option<String> {
+    let note = match date.precision {
+        DatePrecision::Day => return None,
+        DatePrecision::Month => format!(
+            "Start date is approximate, Anilist only lists {}.",
+            date.published.format("%B %Y")
+        ),
+        DatePrecision::Year => format!(
+            "Start date is approximate, Anilist only lists {}.",
+            date.published.format("%Y")
+        ),
+        DatePrecision::Unknown => {
+            "Start date is not announced yet, dated by when it was first seen.".to_string()
+        }
+        DatePrecision::Discovered => format!(
+            "New credit, first seen on Anilist on {}.",
+            date.published.format("%B %-d, %Y")
+        ),
+    };
+    Some(format!("<p><i>{}</i></p>", note))
+}
+
+/// Permanent identifier for a staff on a media, or for a single role when items
+/// aren't merged. It deliberately leaves out anything Anilist users can edit,
+/// like titles or descriptions, so readers don't show the item as new again
+/// after an edit.
+fn staff_media_guid(staff_id: i64, media_id: i64, role: Option<&str>) -> String {
+    let guid = format!(
+        "urn:creator-follower:anilist:staff:{}:media:{}",
+        staff_id, media_id
+    );
+    match role {
+        Some(r) => format!("{}:role:{}", guid, escape_urn_component(r)),
+        None => guid,
+    }
+}
+
+/// Percent encodes everything but unreserved characters so roles such as
+/// "Key Animation (ep 7)" stay valid inside a URN.
+fn escape_urn_component(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+                (b as char).to_string()
+            } else {
+                format!("%{:02X}", b)
+            }
+        })
+        .collect()
+}
+
+/// Escapes text for use inside an html attribute or element.
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Anilist enums serialize to their GraphQL names, e.g. `NOT_YET_RELEASED`.
+fn enum_name<T: Serialize>(value: &T) -> Option<String> {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => Some(name),
+        _ => None,
+    }
+}
+
+/// Joins the present names with ", ", e.g. "Satoshi Kon, 今敏".
+fn join_names(names: Vec<Option<String>>) -> String {
+    names
+        .into_iter()
+        .flatten()
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feeds::FeedFormat;
+    use chrono::TimeZone;
+
+    fn change(
+        kind: MediaChangeKind,
+        old_value: Option<&str>,
+        new_value: Option<&str>,
+    ) -> MediaChange {
+        MediaChange {
+            id: 1,
+            media_id: 1,
+            kind,
+            old_value: old_value.map(str::to_string),
+            new_value: new_value.map(str::to_string),
+            changed_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn media_change_event_names_status_changes() {
+        let started = change(
+            MediaChangeKind::Status,
+            Some("NOT_YET_RELEASED"),
+            Some("RELEASING"),
+        );
+        assert_eq!(
+            media_change_event(&started, Some("ANIME")),
+            "started airing"
+        );
+        assert_eq!(
+            media_change_event(&started, Some("MANGA")),
+            "started publishing"
+        );
+
+        let finished = change(MediaChangeKind::Status, Some("RELEASING"), Some("FINISHED"));
+        assert_eq!(media_change_event(&finished, None), "finished airing");
+
+        let unknown = change(MediaChangeKind::Status, None, Some("NEW_STATUS"));
+        assert_eq!(
+            media_change_event(&unknown, None),
+            "status changed to NEW_STATUS"
+        );
+
+        let removed = change(MediaChangeKind::Status, Some("RELEASING"), None);
+        assert_eq!(media_change_event(&removed, None), "status was removed");
+    }
+
+    #[test]
+    fn media_change_event_tells_confirmed_from_changed_start_dates() {
+        let confirmed = change(MediaChangeKind::StartDate, Some("2024"), Some("2024-04-05"));
+        assert_eq!(
+            media_change_event(&confirmed, None),
+            "start date confirmed for 2024-04-05"
+        );
+
+        let first_known = change(MediaChangeKind::StartDate, None, Some("2024-04"));
+        assert_eq!(
+            media_change_event(&first_known, None),
+            "start date confirmed for 2024-04"
+        );
+
+        let moved = change(MediaChangeKind::StartDate, Some("2024-04"), Some("2024-07"));
+        assert_eq!(
+            media_change_event(&moved, None),
+            "start date changed to 2024-07"
+        );
+
+        let removed = change(MediaChangeKind::StartDate, Some("2024-04"), None);
+        assert_eq!(media_change_event(&removed, None), "start date was removed");
+    }
+
+    #[test]
+    fn parse_staff_ids_drops_repeats_and_blanks() {
+        assert_eq!(parse_staff_ids(" 3, 1,,3 ,2").unwrap(), vec![3, 1, 2]);
+    }
+
+    #[test]
+    fn parse_staff_ids_does_not_repeat_bad_ids() {
+        let error = parse_staff_ids("1,<script>").unwrap_err();
+        match error {
+            ServiceError::InvalidParameter(e) => assert!(!e.message.contains("<script>")),
+            e => panic!("expected an invalid parameter, got {:?}", e),
+        }
+    }
+
+    fn media(id: i64, year: Option<i64>) -> Media {
+        Media {
+            id,
+            title_romaji: Some(format!("Media {}", id)),
+            title_english: None,
+            title_native: None,
+            media_type: Some("ANIME".to_string()),
+            format: Some("TV".to_string()),
+            status: Some("NOT_YET_RELEASED".to_string()),
+            description: None,
+            site_url: None,
+            start_date: FuzzyDate {
+                year,
+                month: None,
+                day: None,
+            },
+        }
+    }
+
+    fn studio_credit(
+        id: i64,
+        year: Option<i64>,
+        first_seen_at: Option<DateTime<Utc>>,
+    ) -> StudioCredit {
+        StudioCredit {
+            is_main: true,
+            media: media(id, year),
+            first_seen_at,
+        }
+    }
+
+    fn studio_filmography(credits: Vec<StudioCredit>) -> StudioFilmography {
+        StudioFilmography {
+            studio: Studio {
+                id: 1,
+                name: "Studio".to_string(),
+                site_url: Some("https://anilist.co/studio/1".to_string()),
+                is_animation_studio: true,
+            },
+            credits,
+        }
+    }
+
+    #[test]
+    fn undated_studio_media_are_dated_by_first_sighting() {
+        let first_seen_at = Utc.with_ymd_and_hms(2026, 10, 1, 0, 0, 0).unwrap();
+        let options = StudioFeedOptions::default();
+        let media_filter = options.media_filter().unwrap();
+        let build = || {
+            build_studio_feed(
+                studio_filmography(vec![
+                    studio_credit(1, Some(2020), None),
+                    studio_credit(2, None, Some(first_seen_at)),
+                ]),
+                &options,
+                &media_filter,
+            )
+            .unwrap()
+        };
+
+        let feed = build();
+        assert_eq!(feed.items[0].published, first_seen_at);
+        assert_eq!(feed.items[0].date_precision, DatePrecision::Unknown);
+        assert_eq!(
+            feed.rendered(FeedFormat::Rss).etag,
+            build().rendered(FeedFormat::Rss).etag
+        );
+    }
+
+    #[test]
+    fn undated_media_without_a_first_sighting_fail() {
+        let options = StudioFeedOptions::default();
+        let result = build_studio_feed(
+            studio_filmography(vec![studio_credit(1, None, None)]),
+            &options,
+            &options.media_filter().unwrap(),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn undated_character_credits_are_dated_by_first_sighting() {
+        let first_seen_at = Utc.with_ymd_and_hms(2026, 10, 1, 0, 0, 0).unwrap();
+        let filmography = StaffFilmography {
+            staff: Staff {
+                id: 1,
+                name_full: Some("Staff".to_string()),
+                name_native: None,
+                site_url: Some("https://anilist.co/staff/1".to_string()),
+                description: None,
+                image_large: Some("https://example.com/staff.png".to_string()),
+            },
+            credits: Vec::new(),
+            first_fetched_at: None,
+            fetched_at: None,
+            media_changes: Vec::new(),
+        };
+        let character_credit = CharacterCredit {
+            character: Character {
+                id: 3,
+                name_full: Some("Character".to_string()),
+                name_native: None,
+                image_large: None,
+                site_url: None,
+            },
+            character_role: Some("MAIN".to_string()),
+            media: media(2, None),
+            first_seen_at: Some(first_seen_at),
+        };
+        let options = StaffFeedOptions {
+            credits: Some(CreditKinds::Character),
+            ..StaffFeedOptions::default()
+        };
+
+        let feed = build_staff_feed(
+            filmography,
+            vec![character_credit],
+            Vec::new(),
+            &options,
+            &options.filters().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(feed.items.len(), 1);
+        assert_eq!(feed.items[0].published, first_seen_at);
+        assert_eq!(feed.items[0].date_precision, DatePrecision::Unknown);
+    }
+
+    #[test]
+    fn undated_character_appearances_are_dated_by_first_sighting() {
+        let first_seen_at = Utc.with_ymd_and_hms(2026, 10, 1, 0, 0, 0).unwrap();
+        let appearance = |id, year, first_seen_at| CharacterAppearance {
+            character_role: Some("MAIN".to_string()),
+            voice_actors: Vec::new(),
+            media: media(id, year),
+            first_seen_at,
+        };
+        let appearances = CharacterAppearances {
+            character: Character {
+                id: 3,
+                name_full: Some("Character".to_string()),
+                name_native: None,
+                image_large: None,
+                site_url: Some("https://anilist.co/character/3".to_string()),
+            },
+            description: None,
+            appearances: vec![
+                appearance(1, Some(2020), None),
+                appearance(2, None, Some(first_seen_at)),
+            ],
+        };
+
+        let options = CharacterFeedOptions::default();
+        let feed =
+            build_character_feed(appearances, &options, &options.media_filter().unwrap()).unwrap();
+        assert_eq!(feed.items.len(), 2);
+        assert_eq!(feed.items[0].published, first_seen_at);
+        assert_eq!(feed.items[0].date_precision, DatePrecision::Unknown);
+    }
+
+    #[test]
+    fn studio_options_read_media_options_from_query() {
+        let options: StudioFeedOptions =
+            serde_urlencoded::from_str("include_undated=false&format=TV&main_studio_only=true")
+                .unwrap();
+        assert_eq!(options.media.include_undated, Some(false));
+        assert_eq!(options.media.format.as_deref(), Some("TV"));
+        assert_eq!(options.main_studio_only, Some(true));
+
+        let result = serde_urlencoded::from_str::<StudioFeedOptions>("include_undated=maybe");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn only_the_latest_episodes_of_each_media_are_kept() {
+        let aired_at = Utc.with_ymd_and_hms(2026, 10, 1, 0, 0, 0).unwrap();
+        let episode = |media_id, episode| AiredEpisode {
+            media_id,
+            episode,
+            aired_at,
+        };
+        let long_running = (1..=40).rev().map(|e| episode(1, e));
+        let short = (1..=3).rev().map(|e| episode(2, e));
+
+        let kept = latest_episodes_per_media(long_running.chain(short).collect());
+        let kept_of = |media_id| {
+            kept.iter()
+                .filter(|e| e.media_id == media_id)
+                .map(|e| e.episode)
+                .collect::<Vec<i64>>()
+        };
+        assert_eq!(kept_of(1), (29..=40).rev().collect::<Vec<i64>>());
+        assert_eq!(kept_of(2), vec![3, 2, 1]);
+    }
+}
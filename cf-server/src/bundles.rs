@@ -0,0 +1,149 @@
+use crate::{
+    anilist_utils::{self, StaffFeedOptions},
+    errors,
+    errors::ServiceError,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+const MAX_SLUG_LENGTH: usize = 64;
+const MAX_NAME_LENGTH: usize = 200;
+const EDIT_TOKEN_BYTES: usize = 32;
+
+/// A saved set of staff and feed options, served as one combined feed under a
+/// short slug.
+#[derive(Debug, Serialize)]
+pub struct Bundle {
+    pub slug: String,
+    pub name: String,
+    pub staff_ids: Vec<i64>,
+    pub options: StaffFeedOptions,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Everything about a bundle its edit token holder may change.
+#[derive(Debug, Deserialize)]
+pub struct BundleInput {
+    pub name: String,
+    pub staff_ids: Vec<i64>,
+    #[serde(default)]
+    pub options: StaffFeedOptions,
+}
+
+impl BundleInput {
+    /// Trims the name, drops repeated staff ids and checks the options the
+    /// same way a feed request would.
+    pub fn validated(self) -> Result<BundleInput, ServiceError> {
+        let name = self.name.trim().to_string();
+        if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+            return Err(errors::invalid_parameter(
+                "name",
+                format!("must be 1 to {} characters", MAX_NAME_LENGTH),
+            ));
+        }
+        let staff_ids = anilist_utils::check_staff_ids(self.staff_ids)?;
+        self.options.filters()?;
+        Ok(BundleInput {
+            name,
+            staff_ids,
+            options: self.options,
+        })
+    }
+}
+
+/// Slugs end up in feed URLs, so they're limited to lowercase letters, digits
+/// and dashes.
+pub fn validate_slug(slug: &str) -> Result<(), ServiceError> {
+    let valid_characters = slug
+        .bytes()
+        .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-');
+    if slug.is_empty()
+        || slug.len() > MAX_SLUG_LENGTH
+        || !valid_characters
+        || slug.starts_with('-')
+        || slug.ends_with('-')
+    {
+        return Err(errors::invalid_parameter(
+            "slug",
+            format!(
+                "must be 1 to {} lowercase letters, digits or inner dashes",
+                MAX_SLUG_LENGTH
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Feed options as the query string a feed URL would carry, e.g.
+/// "include_role=Director&media_type=ANIME".
+pub fn options_to_query(options: &StaffFeedOptions) -> Result<String, ServiceError> {
+    serde_urlencoded::to_string(options)
+        .map_err(|_| errors::internal_logic_error("Feed options don't fit a query string"))
+}
+
+/// Parses feed options from a query string. The parser's message can quote the
+/// input, so it is replaced with a fixed one.
+pub fn options_from_query(query: &str) -> Result<StaffFeedOptions, ServiceError> {
+    serde_urlencoded::from_str(query.trim().trim_start_matches('?')).map_err(|_| {
+        errors::invalid_parameter(
+            "options",
+            "must be feed options as a query string, e.g. include_role=Director".to_string(),
+        )
+    })
+}
+
+/// Random secret handed out once, when a bundle is created. Only its hash is
+/// stored.
+pub fn new_edit_token() -> Result<String, ServiceError> {
+    let mut bytes = [0u8; EDIT_TOKEN_BYTES];
+    getrandom::getrandom(&mut bytes).map_err(|_| ServiceError::InternalError)?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+pub fn edit_token_hash(edit_token: &str) -> String {
+    format!("{:x}", Sha256::digest(edit_token.trim().as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_round_trip_through_a_query_string() {
+        let options = options_from_query("?include_role=Director&one_item_per_role=true").unwrap();
+        assert_eq!(
+            options_to_query(&options).unwrap(),
+            "include_role=Director&one_item_per_role=true"
+        );
+    }
+
+    #[test]
+    fn media_options_round_trip_through_a_query_string() {
+        let query = "include_undated=false&format=TV&one_item_per_role=true";
+        let options = options_from_query(query).unwrap();
+        assert_eq!(options_to_query(&options).unwrap(), query);
+    }
+
+    #[test]
+    fn bundle_options_read_bools_from_json() {
+        let input: BundleInput = serde_json::from_str(
+            r#"{"name": "Bundle", "staff_ids": [1], "options": {"include_undated": false}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            options_to_query(&input.options).unwrap(),
+            "include_undated=false"
+        );
+    }
+
+    #[test]
+    fn bad_options_do_not_repeat_the_input() {
+        let error = options_from_query("one_item_per_role=<b>yes</b>").unwrap_err();
+        match error {
+            ServiceError::InvalidParameter(e) => assert!(!e.message.contains("<b>")),
+            e => panic!("expected an invalid parameter, got {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use crate::clients::{
+    aired_episodes_query, character_media_query, search_query, staff_by_ids_query,
+    staff_character_media_query, staff_media_query, studio_media_query,
+};
+use chrono::{DateTime, Utc};
+use moka::future::Cache;
+use std::env;
+use std::time::Duration;
+
+const STAFF_MEDIA_TTL_ENV: &str = "ANILIST_CACHE_STAFF_MEDIA_TTL_SECONDS";
+const SEARCH_TTL_ENV: &str = "ANILIST_CACHE_SEARCH_TTL_SECONDS";
+const MAX_ENTRIES_ENV: &str = "ANILIST_CACHE_MAX_ENTRIES";
+const DEFAULT_STAFF_MEDIA_TTL_SECONDS: u64 = 60 * 60;
+const DEFAULT_SEARCH_TTL_SECONDS: u64 = 10 * 60;
+const DEFAULT_MAX_ENTRIES: u64 = 10_000;
+
+/// In-process cache of Anilist responses, keyed by query name and variables.
+/// Search responses are kept for the search TTL and everything else for the
+/// staff media TTL. Clones share the same underlying storage.
+#[derive(Clone)]
+pub struct AnilistCache {
+    pub staff_media: Cache<String, Fetched<staff_media_query::ResponseData>>,
+    /// Character roles of staff.
+    pub staff_character_media: Cache<String, Fetched<staff_character_media_query::ResponseData>>,
+    pub search: Cache<String, Fetched<search_query::ResponseData>>,
+    /// Staff looked up by id.
+    pub staff: Cache<String, Fetched<staff_by_ids_query::ResponseData>>,
+    pub character_media: Cache<String, Fetched<character_media_query::ResponseData>>,
+    /// Aired episodes of airing anime.
+    pub aired_episodes: Cache<String, Fetched<aired_episodes_query::ResponseData>>,
+    pub studio_media: Cache<String, Fetched<studio_media_query::ResponseData>>,
+}
+
+/// An Anilist response and when Anilist sent it, which for a cache hit can be
+/// up to a TTL ago.
+#[derive(Clone, Debug)]
+pub struct Fetched<R> {
+    pub data: R,
+    pub fetched_at: DateTime<Utc>,
+}
+
+impl AnilistCache {
+    /// Reads TTLs and the per query entry limit from the environment, falling
+    /// back to the defaults above when a variable is unset or not a number.
+    pub fn from_env() -> Self {
+        let max_entries = env_u64(MAX_ENTRIES_ENV, DEFAULT_MAX_ENTRIES);
+        let staff_media_ttl = env_u64(STAFF_MEDIA_TTL_ENV, DEFAULT_STAFF_MEDIA_TTL_SECONDS);
+        let search_ttl = env_u64(SEARCH_TTL_ENV, DEFAULT_SEARCH_TTL_SECONDS);
+        info!(
+            "Anilist cache: max_entries={}, staff_media_ttl={}s, search_ttl={}s",
+            max_entries, staff_media_ttl, search_ttl
+        );
+
+        AnilistCache {
+            staff_media: Cache::builder()
+                .max_capacity(max_entries)
+                .time_to_live(Duration::from_secs(staff_media_ttl))
+                .build(),
+            staff_character_media: Cache::builder()
+                .max_capacity(max_entries)
+                .time_to_live(Duration::from_secs(staff_media_ttl))
+                .build(),
+            search: Cache::builder()
+                .max_capacity(max_entries)
+                .time_to_live(Duration::from_secs(search_ttl))
+                .build(),
+            staff: Cache::builder()
+                .max_capacity(max_entries)
+                .time_to_live(Duration::from_secs(staff_media_ttl))
+                .build(),
+            character_media: Cache::builder()
+                .max_capacity(max_entries)
+                .time_to_live(Duration::from_secs(staff_media_ttl))
+                .build(),
+            aired_episodes: Cache::builder()
+                .max_capacity(max_entries)
+                .time_to_live(Duration::from_secs(staff_media_ttl))
+                .build(),
+            studio_media: Cache::builder()
+                .max_capacity(max_entries)
+                .time_to_live(Duration::from_secs(staff_media_ttl))
+                .build(),
+        }
+    }
+}
+
+pub(crate) fn env_u64(name: &str, default: u64) -> u64 {
+    env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
@@ -1,18 +1,34 @@
 use crate::{
+    cache::{env_u64, AnilistCache, Fetched},
     errors,
     errors::{AnilistServerError, ServiceError},
+    rate_limiter::AnilistRateLimiter,
 };
-use actix_web::http::StatusCode;
-use graphql_client::{GraphQLQuery, Response};
+use actix_web::{http::StatusCode, rt::time};
+use chrono::Utc;
+use graphql_client::{GraphQLQuery, QueryBody, Response};
+use moka::future::Cache;
 use reqwest::Client;
+use serde::{de::DeserializeOwned, Serialize};
+use std::time::{Duration, Instant};
 
 const ANILIST_GRAPHQL_URL: &str = "https://graphql.anilist.co";
+/// Used when a 429 comes back without a `Retry-After` header.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+const CONNECT_TIMEOUT_ENV: &str = "ANILIST_CONNECT_TIMEOUT_SECONDS";
+const REQUEST_TIMEOUT_ENV: &str = "ANILIST_REQUEST_TIMEOUT_SECONDS";
+const MAX_RETRIES_ENV: &str = "ANILIST_MAX_RETRIES";
+const RETRY_BASE_DELAY_ENV: &str = "ANILIST_RETRY_BASE_DELAY_MILLIS";
+const DEFAULT_CONNECT_TIMEOUT_SECONDS: u64 = 5;
+const DEFAULT_REQUEST_TIMEOUT_SECONDS: u64 = 20;
+const DEFAULT_MAX_RETRIES: u64 = 3;
+const DEFAULT_RETRY_BASE_DELAY_MILLIS: u64 = 500;
 
 #[derive(GraphQLQuery)]
 #[graphql(
     schema_path = "graphql_schemas/anilist-schema.graphql",
     query_path = "graphql_schemas/staff-media-query.graphql",
-    response_derives = "Serialize,Debug"
+    response_derives = "Serialize,Debug,Clone"
 )]
 pub struct StaffMediaQuery;
 
@@ -20,13 +36,121 @@ pub struct StaffMediaQuery;
 #[graphql(
     schema_path = "graphql_schemas/anilist-schema.graphql",
     query_path = "graphql_schemas/search-query.graphql",
-    response_derives = "Serialize,Debug"
+    response_derives = "Serialize,Debug,Clone"
 )]
 pub struct SearchQuery;
 
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "graphql_schemas/anilist-schema.graphql",
+    query_path = "graphql_schemas/staff-character-media-query.graphql",
+    response_derives = "Serialize,Debug,Clone"
+)]
+pub struct StaffCharacterMediaQuery;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "graphql_schemas/anilist-schema.graphql",
+    query_path = "graphql_schemas/staff-by-ids-query.graphql",
+    response_derives = "Serialize,Debug,Clone"
+)]
+pub struct StaffByIdsQuery;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "graphql_schemas/anilist-schema.graphql",
+    query_path = "graphql_schemas/studio-media-query.graphql",
+    response_derives = "Serialize,Debug,Clone"
+)]
+pub struct StudioMediaQuery;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "graphql_schemas/anilist-schema.graphql",
+    query_path = "graphql_schemas/character-media-query.graphql",
+    response_derives = "Serialize,Debug,Clone"
+)]
+pub struct CharacterMediaQuery;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "graphql_schemas/anilist-schema.graphql",
+    query_path = "graphql_schemas/aired-episodes-query.graphql",
+    response_derives = "Serialize,Debug,Clone"
+)]
+pub struct AiredEpisodesQuery;
+
+/// Whether a query may be answered from `AnilistCache`. `Refresh` always asks
+/// Anilist and replaces the cached response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheMode {
+    Cached,
+    Refresh,
+}
+
+/// Exponential backoff for queries that failed in a way worth trying again:
+/// timeouts, dropped connections and 5xx responses.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn from_env() -> Self {
+        let policy = RetryPolicy {
+            max_retries: env_u64(MAX_RETRIES_ENV, DEFAULT_MAX_RETRIES) as u32,
+            base_delay: Duration::from_millis(env_u64(
+                RETRY_BASE_DELAY_ENV,
+                DEFAULT_RETRY_BASE_DELAY_MILLIS,
+            )),
+        };
+        info!(
+            "Anilist retries: max_retries={}, base_delay={}ms",
+            policy.max_retries,
+            policy.base_delay.as_millis()
+        );
+        policy
+    }
+
+    /// Delay before the `retry`th retry, starting at 1. Doubles each time, with
+    /// up to half of it randomized so failed requests don't retry in lockstep.
+    fn delay(&self, retry: u32) -> Duration {
+        let delay = self.base_delay * 2u32.saturating_pow(retry.saturating_sub(1));
+        delay.mul_f64(0.5 + fastrand::f64() * 0.5)
+    }
+
+    /// Delay before the next retry after `retries` so far, or `None` once
+    /// they're used up or waiting would pass `deadline`.
+    fn next_delay(&self, retries: u32, now: Instant, deadline: Instant) -> Option<Duration> {
+        if retries >= self.max_retries {
+            return None;
+        }
+        Some(self.delay(retries + 1)).filter(|delay| now + *delay <= deadline)
+    }
+}
+
+/// Http client with the connect and overall timeouts from the environment, so a
+/// hung Anilist connection can't hold a feed request open forever.
+pub fn http_client_from_env() -> reqwest::Result<Client> {
+    let connect_timeout = env_u64(CONNECT_TIMEOUT_ENV, DEFAULT_CONNECT_TIMEOUT_SECONDS);
+    let request_timeout = env_u64(REQUEST_TIMEOUT_ENV, DEFAULT_REQUEST_TIMEOUT_SECONDS);
+    info!(
+        "Anilist timeouts: connect={}s, request={}s",
+        connect_timeout, request_timeout
+    );
+    Client::builder()
+        .connect_timeout(Duration::from_secs(connect_timeout))
+        .timeout(Duration::from_secs(request_timeout))
+        .build()
+}
+
 #[derive(Clone)]
 pub struct AnilistClient {
     pub client: Client,
+    pub cache: AnilistCache,
+    pub rate_limiter: AnilistRateLimiter,
+    pub retry_policy: RetryPolicy,
 }
 
 impl AnilistClient {
@@ -35,7 +159,8 @@ impl AnilistClient {
         id: i64,
         staff_media_per_page: i64,
         staff_media_page: i64,
-    ) -> Result<staff_media_query::ResponseData, ServiceError> {
+        cache_mode: CacheMode,
+    ) -> Result<Fetched<staff_media_query::ResponseData>, ServiceError> {
         let staff_media_query_variables: staff_media_query::Variables =
             staff_media_query::Variables {
                 id: Some(id),
@@ -44,59 +169,227 @@ impl AnilistClient {
             };
         let staff_media_request = StaffMediaQuery::build_query(staff_media_query_variables);
 
-        let res = self
-            .client
-            .post(ANILIST_GRAPHQL_URL)
-            .json(&staff_media_request)
-            .send()
-            .await?;
-        let status_code = &StatusCode::from_u16(res.status().as_u16())
-            .expect("Failed to get Anilist Status Code");
+        self.cached_post(&self.cache.staff_media, &staff_media_request, cache_mode)
+            .await
+    }
 
-        let response_body: Response<staff_media_query::ResponseData> = res.json().await?;
-        if response_body.errors.is_some() {
-            let errors = response_body.errors.ok_or(errors::internal_logic_error(
-                "response_body.errors is None after check",
-            ))?;
+    pub async fn get_staff_character_media(
+        &self,
+        id: i64,
+        character_media_per_page: i64,
+        character_media_page: i64,
+        cache_mode: CacheMode,
+    ) -> Result<staff_character_media_query::ResponseData, ServiceError> {
+        let variables = staff_character_media_query::Variables {
+            id: Some(id),
+            character_media_per_page: Some(character_media_per_page),
+            character_media_page: Some(character_media_page),
+        };
+        let character_media_request = StaffCharacterMediaQuery::build_query(variables);
 
-            let first = errors
-                .into_iter()
-                .nth(0)
-                .ok_or(errors::anilist_data_format(
-                    "response_body.errors exists but is empty",
-                ))?;
-            let anilist_error = AnilistServerError {
-                message: first.message,
-                status_code: *status_code,
-            };
+        self.cached_post(
+            &self.cache.staff_character_media,
+            &character_media_request,
+            cache_mode,
+        )
+        .await
+        .map(|fetched| fetched.data)
+    }
 
-            return Err(ServiceError::from(anilist_error));
-        }
-        response_body
-            .data
-            .ok_or(errors::anilist_data_format("Data is None"))
+    pub async fn get_studio_media(
+        &self,
+        id: i64,
+        studio_media_per_page: i64,
+        studio_media_page: i64,
+        cache_mode: CacheMode,
+    ) -> Result<studio_media_query::ResponseData, ServiceError> {
+        let variables = studio_media_query::Variables {
+            id: Some(id),
+            studio_media_per_page: Some(studio_media_per_page),
+            studio_media_page: Some(studio_media_page),
+        };
+        let studio_media_request = StudioMediaQuery::build_query(variables);
+
+        self.cached_post(&self.cache.studio_media, &studio_media_request, cache_mode)
+            .await
+            .map(|fetched| fetched.data)
     }
 
+    pub async fn get_character_media(
+        &self,
+        id: i64,
+        character_media_per_page: i64,
+        character_media_page: i64,
+        cache_mode: CacheMode,
+    ) -> Result<character_media_query::ResponseData, ServiceError> {
+        let variables = character_media_query::Variables {
+            id: Some(id),
+            character_media_per_page: Some(character_media_per_page),
+            character_media_page: Some(character_media_page),
+        };
+        let character_media_request = CharacterMediaQuery::build_query(variables);
+
+        self.cached_post(
+            &self.cache.character_media,
+            &character_media_request,
+            cache_mode,
+        )
+        .await
+        .map(|fetched| fetched.data)
+    }
+
+    /// One page of the episodes of the given media that aired after the
+    /// `aired_after` Unix timestamp, newest first.
+    pub async fn get_aired_episodes(
+        &self,
+        media_ids: &[i64],
+        aired_after: i64,
+        episodes_per_page: i64,
+        episodes_page: i64,
+        cache_mode: CacheMode,
+    ) -> Result<aired_episodes_query::ResponseData, ServiceError> {
+        let variables = aired_episodes_query::Variables {
+            media_ids: Some(media_ids.iter().map(|id| Some(*id)).collect()),
+            aired_after: Some(aired_after),
+            episodes_per_page: Some(episodes_per_page),
+            episodes_page: Some(episodes_page),
+        };
+        let aired_episodes_request = AiredEpisodesQuery::build_query(variables);
+
+        self.cached_post(
+            &self.cache.aired_episodes,
+            &aired_episodes_request,
+            cache_mode,
+        )
+        .await
+        .map(|fetched| fetched.data)
+    }
+
+    /// Searches staff and studios with the same query, `per_page` of each.
     pub async fn search(
         &self,
         query: &str,
-        staff_per_page: i64,
+        per_page: i64,
     ) -> Result<search_query::ResponseData, ServiceError> {
         let variables: search_query::Variables = search_query::Variables {
             search: Some(query.to_string()),
-            staff_per_page: Some(staff_per_page),
+            staff_per_page: Some(per_page),
+            studio_per_page: Some(per_page),
         };
 
         let search_request = SearchQuery::build_query(variables);
-        let res = self
-            .client
-            .post(ANILIST_GRAPHQL_URL)
-            .json(&search_request)
-            .send()
-            .await?;
+        self.cached_post(&self.cache.search, &search_request, CacheMode::Cached)
+            .await
+            .map(|fetched| fetched.data)
+    }
+
+    /// Looks up the given staff in one request, in no particular order. Anilist
+    /// returns at most 50 staff per page.
+    pub async fn get_staff_by_ids(
+        &self,
+        ids: &[i64],
+    ) -> Result<staff_by_ids_query::ResponseData, ServiceError> {
+        let variables = staff_by_ids_query::Variables {
+            ids: Some(ids.iter().map(|id| Some(*id)).collect()),
+            staff_per_page: Some(ids.len() as i64),
+        };
+
+        let staff_by_ids_request = StaffByIdsQuery::build_query(variables);
+        self.cached_post(&self.cache.staff, &staff_by_ids_request, CacheMode::Cached)
+            .await
+            .map(|fetched| fetched.data)
+    }
+
+    /// Answers from `cache` when an identical query was made within its TTL,
+    /// otherwise asks Anilist and caches the result.
+    async fn cached_post<V: Serialize, R: DeserializeOwned + Clone + Send + Sync + 'static>(
+        &self,
+        cache: &Cache<String, Fetched<R>>,
+        request: &QueryBody<V>,
+        cache_mode: CacheMode,
+    ) -> Result<Fetched<R>, ServiceError> {
+        let cache_key = format!(
+            "{}:{}",
+            request.operation_name,
+            serde_json::to_string(&request.variables)?
+        );
+        if cache_mode == CacheMode::Cached {
+            if let Some(data) = cache.get(&cache_key).await {
+                info!("Anilist cache hit: {}", cache_key);
+                return Ok(data);
+            }
+            info!("Anilist cache miss: {}", cache_key);
+        } else {
+            info!("Anilist cache refresh: {}", cache_key);
+        }
+
+        let fetched = Fetched {
+            data: self.post(request).await?,
+            fetched_at: Utc::now(),
+        };
+        cache.insert(cache_key, fetched.clone()).await;
+        Ok(fetched)
+    }
+
+    /// Sends the query once the rate limiter allows it. 429s are retried after
+    /// their `Retry-After` for as long as `rate_limiter.max_wait` allows, and
+    /// transient failures are retried as `retry_policy` says. Every query is
+    /// read only, so retrying one is always safe.
+    async fn post<V: Serialize, R: DeserializeOwned>(
+        &self,
+        request: &QueryBody<V>,
+    ) -> Result<R, ServiceError> {
+        let deadline = Instant::now() + self.rate_limiter.max_wait;
+        let mut retries = 0;
+        let res = loop {
+            self.rate_limiter.acquire(deadline).await?;
+            let res = match self
+                .client
+                .post(ANILIST_GRAPHQL_URL)
+                .json(request)
+                .send()
+                .await
+            {
+                Ok(res) => res,
+                Err(e) => {
+                    if is_transient(&e)
+                        && self.back_off(&mut retries, deadline, &e.to_string()).await
+                    {
+                        continue;
+                    }
+                    return Err(e.into());
+                }
+            };
+            let retry_after = self.rate_limiter.update(res.headers());
+            if res.status().is_server_error() {
+                if self
+                    .back_off(&mut retries, deadline, res.status().as_str())
+                    .await
+                {
+                    continue;
+                }
+                // The body is as likely an error page as GraphQL errors.
+                return Err(ServiceError::from(AnilistServerError {
+                    message: format!("Anilist responded with {}", res.status()),
+                    status_code: StatusCode::BAD_GATEWAY,
+                }));
+            }
+            if res.status() != reqwest::StatusCode::TOO_MANY_REQUESTS {
+                break res;
+            }
+            let retry_after = retry_after.unwrap_or(DEFAULT_RETRY_AFTER);
+            if Instant::now() + retry_after > deadline {
+                return Err(errors::anilist_rate_limited(retry_after));
+            }
+            warn!(
+                "Anilist rate limited, retrying in {}s",
+                retry_after.as_secs()
+            );
+        };
         let status_code = &StatusCode::from_u16(res.status().as_u16())
             .expect("Failed to get Anilist Status Code");
-        let response_body: Response<search_query::ResponseData> = res.json().await?;
+
+        let response_body: Response<R> = res.json().await?;
         if response_body.errors.is_some() {
             let errors = response_body.errors.ok_or(errors::internal_logic_error(
                 "response_body.errors is None after check",
@@ -104,7 +397,7 @@ impl AnilistClient {
 
             let first = errors
                 .into_iter()
-                .nth(0)
+                .next()
                 .ok_or(errors::anilist_data_format(
                     "response_body.errors exists but is empty",
                 ))?;
@@ -119,4 +412,63 @@ impl AnilistClient {
             .data
             .ok_or(errors::anilist_data_format("Data is None"))
     }
+
+    /// Waits out the delay before the next retry and counts it, or returns
+    /// false right away when the request should give up instead.
+    async fn back_off(&self, retries: &mut u32, deadline: Instant, reason: &str) -> bool {
+        let Some(delay) = self
+            .retry_policy
+            .next_delay(*retries, Instant::now(), deadline)
+        else {
+            warn!("Anilist request failed ({}), giving up", reason);
+            return false;
+        };
+        *retries += 1;
+        warn!(
+            "Anilist request failed ({}), retry {}/{} in {}ms",
+            reason,
+            retries,
+            self.retry_policy.max_retries,
+            delay.as_millis()
+        );
+        time::sleep(delay).await;
+        true
+    }
+}
+
+/// Failures that say nothing about the query itself and may well succeed on
+/// a second try.
+fn is_transient(e: &reqwest::Error) -> bool {
+    e.is_timeout() || e.is_connect() || e.is_request() || e.is_body()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: RetryPolicy = RetryPolicy {
+        max_retries: 3,
+        base_delay: Duration::from_millis(500),
+    };
+
+    #[test]
+    fn retries_stop_when_used_up() {
+        let now = Instant::now();
+        let deadline = now + Duration::from_secs(60);
+        assert!(POLICY.next_delay(0, now, deadline).is_some());
+        assert!(POLICY.next_delay(2, now, deadline).is_some());
+        assert!(POLICY.next_delay(3, now, deadline).is_none());
+    }
+
+    #[test]
+    fn retries_stop_before_the_deadline() {
+        let now = Instant::now();
+        // The third retry waits 1 to 2 seconds.
+        assert!(POLICY
+            .next_delay(2, now, now + Duration::from_millis(900))
+            .is_none());
+        assert!(POLICY
+            .next_delay(2, now, now + Duration::from_secs(2))
+            .is_some());
+    }
 }
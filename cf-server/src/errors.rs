@@ -1,14 +1,18 @@
 use actix_web::{
     error,
-    http::{header::ContentType, StatusCode},
+    http::{
+        header::{ContentType, RETRY_AFTER},
+        StatusCode,
+    },
     HttpResponse,
 };
 use askama::Error as AskamaError;
 use derive_more::{Display, Error};
-use reqwest;
 use std::convert::From;
 use std::fmt;
 use std::num::TryFromIntError;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Debug, Display, Error)]
 pub struct ErrorMessageWrapper {
@@ -27,6 +31,67 @@ impl fmt::Display for AnilistServerError {
     }
 }
 
+#[derive(Debug, Error)]
+pub struct AnilistRateLimitedError {
+    pub retry_after: Duration,
+}
+
+impl AnilistRateLimitedError {
+    /// Whole seconds to wait, rounded up so clients don't retry too early.
+    pub fn retry_after_seconds(&self) -> u64 {
+        self.retry_after.as_secs() + u64::from(self.retry_after.subsec_nanos() > 0)
+    }
+}
+
+impl fmt::Display for AnilistRateLimitedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "retry after {} seconds", self.retry_after_seconds())
+    }
+}
+
+/// An error handed to every request that waited on the same `SingleFlight`
+/// build. It responds exactly like the error it wraps.
+#[derive(Clone, Debug)]
+pub struct SharedServiceError(pub Arc<ServiceError>);
+
+impl fmt::Display for SharedServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for SharedServiceError {}
+
+impl From<ServiceError> for SharedServiceError {
+    fn from(e: ServiceError) -> SharedServiceError {
+        SharedServiceError(Arc::new(e))
+    }
+}
+
+impl From<SharedServiceError> for ServiceError {
+    fn from(e: SharedServiceError) -> ServiceError {
+        ServiceError::Shared(e)
+    }
+}
+
+#[derive(Debug, Error)]
+pub struct InvalidParameterError {
+    pub parameter: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for InvalidParameterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.parameter, self.message)
+    }
+}
+
+impl From<InvalidParameterError> for ServiceError {
+    fn from(e: InvalidParameterError) -> ServiceError {
+        ServiceError::InvalidParameter(e)
+    }
+}
+
 impl From<AnilistServerError> for ServiceError {
     fn from(e: AnilistServerError) -> ServiceError {
         ServiceError::AnilistError(e)
@@ -39,8 +104,23 @@ impl From<TryFromIntError> for ServiceError {
     }
 }
 
+impl From<sqlx::Error> for ServiceError {
+    fn from(e: sqlx::Error) -> ServiceError {
+        ServiceError::DatabaseError(e)
+    }
+}
+
+impl From<serde_json::Error> for ServiceError {
+    fn from(_e: serde_json::Error) -> ServiceError {
+        ServiceError::InternalError
+    }
+}
+
 impl From<reqwest::Error> for ServiceError {
     fn from(e: reqwest::Error) -> ServiceError {
+        if e.is_timeout() {
+            return ServiceError::AnilistTimeout;
+        }
         if e.is_status() {
             if let Some(s) = e.status() {
                 return ServiceError::AnilistError(AnilistServerError {
@@ -67,15 +147,44 @@ pub enum ServiceError {
     #[display(fmt = "An error occurred in Anilist: {}", _0)]
     AnilistError(AnilistServerError),
 
+    #[display(fmt = "Anilist took too long to respond. Please try again later")]
+    AnilistTimeout,
+
+    #[display(fmt = "Anilist rate limit reached, {}", _0)]
+    AnilistRateLimited(AnilistRateLimitedError),
+
     #[display(fmt = "An internal error occurred. Please try again later")]
     AskamaError(AskamaError),
+
+    #[display(fmt = "Invalid query parameter {}", _0)]
+    InvalidParameter(InvalidParameterError),
+
+    #[display(fmt = "An internal error occurred. Please try again later")]
+    DatabaseError(sqlx::Error),
+
+    #[display(fmt = "{}", _0)]
+    Shared(SharedServiceError),
+
+    #[display(fmt = "Not found: {}", _0)]
+    NotFound(ErrorMessageWrapper),
+
+    #[display(fmt = "Forbidden: {}", _0)]
+    Forbidden(ErrorMessageWrapper),
 }
 
 impl error::ResponseError for ServiceError {
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code())
-            .insert_header(ContentType::html())
-            .body(self.to_string())
+        if let ServiceError::Shared(e) = self {
+            return e.0.error_response();
+        }
+        let mut response = HttpResponse::build(self.status_code());
+        // Messages can repeat user input, e.g. a rejected regex, so they must
+        // never be rendered as html.
+        response.insert_header(ContentType::plaintext());
+        if let ServiceError::AnilistRateLimited(e) = self {
+            response.insert_header((RETRY_AFTER, e.retry_after_seconds()));
+        }
+        response.body(self.to_string())
     }
 
     fn status_code(&self) -> StatusCode {
@@ -84,7 +193,14 @@ impl error::ResponseError for ServiceError {
             ServiceError::AnilistDataFormat(_e) => StatusCode::INTERNAL_SERVER_ERROR,
             ServiceError::InternalLogicError(_e) => StatusCode::INTERNAL_SERVER_ERROR,
             ServiceError::AnilistError(e) => e.status_code,
+            ServiceError::AnilistTimeout => StatusCode::GATEWAY_TIMEOUT,
+            ServiceError::AnilistRateLimited(_e) => StatusCode::SERVICE_UNAVAILABLE,
             ServiceError::AskamaError(_e) => StatusCode::INTERNAL_SERVER_ERROR,
+            ServiceError::InvalidParameter(_e) => StatusCode::BAD_REQUEST,
+            ServiceError::DatabaseError(_e) => StatusCode::INTERNAL_SERVER_ERROR,
+            ServiceError::Shared(e) => e.0.status_code(),
+            ServiceError::NotFound(_e) => StatusCode::NOT_FOUND,
+            ServiceError::Forbidden(_e) => StatusCode::FORBIDDEN,
         }
     }
 }
@@ -96,3 +212,19 @@ pub fn anilist_data_format(message: &'static str) -> ServiceError {
 pub fn internal_logic_error(message: &'static str) -> ServiceError {
     ServiceError::InternalLogicError(ErrorMessageWrapper { message })
 }
+
+pub fn not_found(message: &'static str) -> ServiceError {
+    ServiceError::NotFound(ErrorMessageWrapper { message })
+}
+
+pub fn forbidden(message: &'static str) -> ServiceError {
+    ServiceError::Forbidden(ErrorMessageWrapper { message })
+}
+
+pub fn invalid_parameter(parameter: &'static str, message: String) -> ServiceError {
+    ServiceError::InvalidParameter(InvalidParameterError { parameter, message })
+}
+
+pub fn anilist_rate_limited(retry_after: Duration) -> ServiceError {
+    ServiceError::AnilistRateLimited(AnilistRateLimitedError { retry_after })
+}
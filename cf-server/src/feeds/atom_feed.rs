@@ -0,0 +1,53 @@
+use super::Feed;
+use atom_syndication::{
+    Content, ContentBuilder, Entry, EntryBuilder, Feed as AtomFeed, FeedBuilder, LinkBuilder,
+    PersonBuilder, Text,
+};
+use chrono::{DateTime, Utc};
+
+const HTML_CONTENT_TYPE: &str = "html";
+
+pub fn to_feed(feed: &Feed, now: DateTime<Utc>) -> AtomFeed {
+    // Atom requires `updated`. An empty feed gets a fixed one so rendering it
+    // again gives the same body and ETag.
+    let updated = feed.updated(now).unwrap_or(DateTime::<Utc>::UNIX_EPOCH);
+
+    let entries: Vec<Entry> = feed
+        .items
+        .iter()
+        .map(|item| {
+            let links = item
+                .link
+                .iter()
+                .map(|l| LinkBuilder::default().href(l.clone()).build())
+                .collect::<Vec<_>>();
+            let content: Option<Content> = item.description.as_ref().map(|d| {
+                ContentBuilder::default()
+                    .value(Some(d.clone()))
+                    .content_type(Some(HTML_CONTENT_TYPE.to_string()))
+                    .build()
+            });
+
+            EntryBuilder::default()
+                .id(item.guid.clone())
+                .title(Text::plain(item.title.clone()))
+                .updated(item.published)
+                .published(Some(item.published.into()))
+                .links(links)
+                .content(content)
+                .build()
+        })
+        .collect();
+
+    FeedBuilder::default()
+        .id(feed.link.clone())
+        .title(Text::plain(feed.title.clone()))
+        .subtitle(Some(Text::html(feed.description.clone())))
+        .updated(updated)
+        .author(PersonBuilder::default().name(feed.title.clone()).build())
+        .link(LinkBuilder::default().href(feed.link.clone()).build())
+        .logo(Some(feed.image_url.clone()))
+        .icon(Some(feed.image_url.clone()))
+        .entries(entries)
+        .build()
+}
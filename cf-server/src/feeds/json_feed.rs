@@ -0,0 +1,88 @@
+use super::{DatePrecision, Feed};
+use serde::Serialize;
+
+const JSON_FEED_VERSION: &str = "https://jsonfeed.org/version/1.1";
+
+/// https://www.jsonfeed.org/version/1.1/
+#[derive(Serialize)]
+struct JsonFeed<'a> {
+    version: &'static str,
+    title: &'a str,
+    home_page_url: &'a str,
+    description: &'a str,
+    icon: &'a str,
+    authors: Vec<JsonFeedAuthor<'a>>,
+    items: Vec<JsonFeedItem<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonFeedAuthor<'a> {
+    name: &'a str,
+    url: &'a str,
+    avatar: &'a str,
+}
+
+#[derive(Serialize)]
+struct JsonFeedItem<'a> {
+    id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<&'a str>,
+    title: &'a str,
+    content_html: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<&'a str>,
+    date_published: String,
+    #[serde(rename = "_creator_follower")]
+    creator_follower: CreatorFollowerExtension<'a>,
+}
+
+/// Custom JSON Feed extension carrying the Anilist specific fields of an item.
+#[derive(Serialize)]
+struct CreatorFollowerExtension<'a> {
+    role: Option<&'a str>,
+    media_type: Option<&'a str>,
+    format: Option<&'a str>,
+    status: Option<&'a str>,
+    date_precision: DatePrecision,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    first_seen_at: Option<String>,
+}
+
+pub fn to_json(feed: &Feed) -> String {
+    let items: Vec<JsonFeedItem> = feed
+        .items
+        .iter()
+        .map(|item| JsonFeedItem {
+            id: &item.guid,
+            url: item.link.as_deref(),
+            title: &item.title,
+            content_html: item.description.as_deref().unwrap_or_default(),
+            image: item.image_url.as_deref(),
+            date_published: item.published.to_rfc3339(),
+            creator_follower: CreatorFollowerExtension {
+                role: item.role.as_deref(),
+                media_type: item.media_type.as_deref(),
+                format: item.format.as_deref(),
+                status: item.status.as_deref(),
+                date_precision: item.date_precision,
+                first_seen_at: item.first_seen_at.map(|d| d.to_rfc3339()),
+            },
+        })
+        .collect();
+
+    let json_feed = JsonFeed {
+        version: JSON_FEED_VERSION,
+        title: &feed.title,
+        home_page_url: &feed.link,
+        description: &feed.description,
+        icon: &feed.image_url,
+        authors: vec![JsonFeedAuthor {
+            name: &feed.title,
+            url: &feed.link,
+            avatar: &feed.image_url,
+        }],
+        items,
+    };
+
+    serde_json::to_string(&json_feed).expect("JsonFeed only contains serializable fields")
+}
@@ -0,0 +1,345 @@
+mod atom_feed;
+mod json_feed;
+mod rss_feed;
+
+use actix_web::{
+    http::header::{
+        self, CacheControl, CacheDirective, ContentType, ETag, EntityTag, Header, HttpDate,
+        IfModifiedSince, IfNoneMatch, LastModified,
+    },
+    HttpRequest, HttpResponse, HttpResponseBuilder,
+};
+use chrono::{DateTime, SubsecRound, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::time::SystemTime;
+
+const ATOM_MIME_TYPE: &str = "application/atom+xml";
+const JSON_FEED_MIME_TYPE: &str = "application/feed+json";
+const JSON_MIME_TYPE: &str = "application/json";
+const RSS_MIME_TYPE: &str = "application/rss+xml";
+/// How long readers and caches may keep a feed before asking again, sent as
+/// the RSS `ttl` and the `Cache-Control` max-age.
+const FEED_TTL_MINUTES: u64 = 360;
+
+/// Source-agnostic description of a feed. Route handlers build one of these and
+/// the renderers below turn it into RSS, Atom or JSON Feed.
+pub struct Feed {
+    pub title: String,
+    pub link: String,
+    pub description: String,
+    pub image_url: String,
+    pub items: Vec<FeedItem>,
+}
+
+pub struct FeedItem {
+    /// Permanent, non permalink identifier of the item.
+    pub guid: String,
+    pub title: String,
+    pub link: Option<String>,
+    pub description: Option<String>,
+    pub published: DateTime<Utc>,
+    pub date_precision: DatePrecision,
+    pub role: Option<String>,
+    pub media_type: Option<String>,
+    pub format: Option<String>,
+    pub status: Option<String>,
+    pub first_seen_at: Option<DateTime<Utc>>,
+    /// Picture of what the item is about, e.g. the character a staff voiced.
+    pub image_url: Option<String>,
+}
+
+/// How much of `FeedItem::published` comes from the source rather than being
+/// filled in as a best guess.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DatePrecision {
+    Day,
+    Month,
+    Year,
+    Unknown,
+    /// Dated by when the item was first seen rather than by the source.
+    Discovered,
+}
+
+impl Feed {
+    /// Newest item date up to `now`, used as the feed level `updated`
+    /// timestamp. Upcoming media are dated by their start date, which would
+    /// otherwise date the feed months ahead.
+    pub fn updated(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.items
+            .iter()
+            .map(|i| i.published)
+            .filter(|d| *d <= now)
+            .max()
+    }
+
+    pub fn render(&self, format: FeedFormat, now: DateTime<Utc>) -> String {
+        match format {
+            FeedFormat::Rss => rss_feed::to_channel(self, now).to_string(),
+            FeedFormat::Atom => atom_feed::to_feed(self, now).to_string(),
+            FeedFormat::Json => json_feed::to_json(self),
+        }
+    }
+
+    pub fn rendered(&self, format: FeedFormat) -> RenderedFeed {
+        let now = Utc::now();
+        let body = self.render(format, now);
+        let etag = EntityTag::new_strong(format!("{:x}", Sha256::digest(body.as_bytes())));
+        RenderedFeed {
+            format,
+            body,
+            etag,
+            // HTTP dates only have whole seconds.
+            last_modified: self
+                .last_modified(now)
+                .map(|d| SystemTime::from(d.trunc_subsecs(0))),
+        }
+    }
+
+    /// Newest item date or first sighting, so a credit added for an old media
+    /// still counts as a change. Upcoming media are dated by their start date,
+    /// so dates after `now` are skipped; clamping them to `now` instead would
+    /// change the value on every build.
+    fn last_modified(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.items
+            .iter()
+            .flat_map(|i| [Some(i.published), i.first_seen_at])
+            .flatten()
+            .filter(|d| *d <= now)
+            .max()
+    }
+}
+
+/// A feed rendered in one format, ready to be sent to any number of readers.
+pub struct RenderedFeed {
+    pub format: FeedFormat,
+    pub body: String,
+    /// Strong validator, a hash of `body`.
+    pub etag: EntityTag,
+    /// See `Feed::last_modified`.
+    pub last_modified: Option<SystemTime>,
+}
+
+impl RenderedFeed {
+    /// The full feed, or an empty 304 when the reader's `If-None-Match` or
+    /// `If-Modified-Since` shows it already has this version.
+    pub fn to_response(&self, req: &HttpRequest) -> HttpResponse {
+        if self.is_not_modified(req) {
+            return self.with_validators(HttpResponse::NotModified()).finish();
+        }
+        self.with_validators(HttpResponse::Ok())
+            .content_type(self.format.content_type())
+            .body(self.body.clone())
+    }
+
+    /// `If-None-Match` wins over `If-Modified-Since` when both are sent, as RFC
+    /// 9110 asks.
+    fn is_not_modified(&self, req: &HttpRequest) -> bool {
+        if req.headers().contains_key(header::IF_NONE_MATCH) {
+            return match IfNoneMatch::parse(req) {
+                Ok(IfNoneMatch::Any) => true,
+                Ok(IfNoneMatch::Items(etags)) => etags.iter().any(|e| e.weak_eq(&self.etag)),
+                Err(_) => false,
+            };
+        }
+        match (IfModifiedSince::parse(req), self.last_modified) {
+            (Ok(IfModifiedSince(since)), Some(last_modified)) => {
+                HttpDate::from(last_modified) <= since
+            }
+            _ => false,
+        }
+    }
+
+    fn with_validators(&self, mut builder: HttpResponseBuilder) -> HttpResponseBuilder {
+        builder
+            .insert_header(ETag(self.etag.clone()))
+            .insert_header(CacheControl(vec![
+                CacheDirective::Public,
+                CacheDirective::MaxAge((FEED_TTL_MINUTES * 60) as u32),
+            ]));
+        if let Some(last_modified) = self.last_modified {
+            builder.insert_header(LastModified(last_modified.into()));
+        }
+        builder
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeedFormat {
+    Rss,
+    Atom,
+    Json,
+}
+
+impl FeedFormat {
+    /// Picks a format from the request's `Accept` header, falling back to RSS
+    /// when nothing more specific is asked for.
+    pub fn negotiate(req: &HttpRequest) -> FeedFormat {
+        let accept = match header::Accept::parse(req) {
+            Ok(a) => a,
+            Err(_) => return FeedFormat::Rss,
+        };
+        accept
+            .ranked()
+            .into_iter()
+            .find_map(|mime| match mime.essence_str() {
+                ATOM_MIME_TYPE => Some(FeedFormat::Atom),
+                JSON_FEED_MIME_TYPE | JSON_MIME_TYPE => Some(FeedFormat::Json),
+                RSS_MIME_TYPE => Some(FeedFormat::Rss),
+                _ => None,
+            })
+            .unwrap_or(FeedFormat::Rss)
+    }
+
+    pub fn content_type(&self) -> ContentType {
+        match self {
+            FeedFormat::Rss => ContentType::xml(),
+            FeedFormat::Atom => ContentType(
+                ATOM_MIME_TYPE
+                    .parse()
+                    .expect("Atom mime type is a valid mime"),
+            ),
+            FeedFormat::Json => ContentType(
+                JSON_FEED_MIME_TYPE
+                    .parse()
+                    .expect("JSON Feed mime type is a valid mime"),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use chrono::{Duration, TimeZone};
+
+    fn item(published: DateTime<Utc>, first_seen_at: Option<DateTime<Utc>>) -> FeedItem {
+        FeedItem {
+            guid: "guid".to_string(),
+            title: "Title".to_string(),
+            link: None,
+            description: None,
+            published,
+            date_precision: DatePrecision::Day,
+            role: None,
+            media_type: None,
+            format: None,
+            status: None,
+            first_seen_at,
+            image_url: None,
+        }
+    }
+
+    fn feed(items: Vec<FeedItem>) -> Feed {
+        Feed {
+            title: "Feed".to_string(),
+            link: "https://anilist.co/staff/1".to_string(),
+            description: String::new(),
+            image_url: String::new(),
+            items,
+        }
+    }
+
+    fn rendered(last_modified: Option<SystemTime>) -> RenderedFeed {
+        RenderedFeed {
+            format: FeedFormat::Rss,
+            body: String::new(),
+            etag: EntityTag::new_strong("abc".to_string()),
+            last_modified,
+        }
+    }
+
+    fn http_date(d: DateTime<Utc>) -> String {
+        d.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+    }
+
+    #[test]
+    fn last_modified_counts_first_sightings() {
+        let published = Utc.with_ymd_and_hms(2010, 4, 1, 0, 0, 0).unwrap();
+        let first_seen_at = Utc.with_ymd_and_hms(2026, 10, 1, 0, 0, 0).unwrap();
+        let now = Utc.with_ymd_and_hms(2026, 10, 18, 0, 0, 0).unwrap();
+        let feed = feed(vec![item(published, Some(first_seen_at))]);
+        assert_eq!(feed.last_modified(now), Some(first_seen_at));
+    }
+
+    #[test]
+    fn last_modified_skips_upcoming_items() {
+        let now = Utc.with_ymd_and_hms(2026, 10, 18, 0, 0, 0).unwrap();
+        let published = Utc.with_ymd_and_hms(2026, 4, 1, 0, 0, 0).unwrap();
+        let mixed = feed(vec![
+            item(now + Duration::days(90), None),
+            item(published, None),
+        ]);
+        assert_eq!(mixed.last_modified(now), Some(published));
+        assert_eq!(
+            mixed.last_modified(now + Duration::hours(1)),
+            Some(published)
+        );
+
+        let upcoming = feed(vec![item(now + Duration::days(90), None)]);
+        assert_eq!(upcoming.last_modified(now), None);
+        assert_eq!(feed(Vec::new()).last_modified(now), None);
+    }
+
+    #[test]
+    fn updated_skips_upcoming_items() {
+        let now = Utc.with_ymd_and_hms(2026, 10, 18, 0, 0, 0).unwrap();
+        let published = Utc.with_ymd_and_hms(2026, 4, 1, 0, 0, 0).unwrap();
+        let mixed = feed(vec![
+            item(now + Duration::days(90), None),
+            item(published, None),
+        ]);
+        assert_eq!(mixed.updated(now), Some(published));
+
+        let rss = mixed.render(FeedFormat::Rss, now);
+        assert!(rss.contains(&format!("<pubDate>{}</pubDate>", published.to_rfc2822())));
+        assert!(rss.contains(&format!(
+            "<lastBuildDate>{}</lastBuildDate>",
+            published.to_rfc2822()
+        )));
+        let atom = mixed.render(FeedFormat::Atom, now);
+        assert!(atom.contains(&format!("<updated>{}</updated>", published.to_rfc3339())));
+    }
+
+    #[test]
+    fn matching_etag_is_not_modified() {
+        let feed = rendered(None);
+        let req = TestRequest::default()
+            .insert_header((header::IF_NONE_MATCH, "\"xyz\", \"abc\""))
+            .to_http_request();
+        assert!(feed.is_not_modified(&req));
+
+        let req = TestRequest::default()
+            .insert_header((header::IF_NONE_MATCH, "*"))
+            .to_http_request();
+        assert!(feed.is_not_modified(&req));
+    }
+
+    #[test]
+    fn if_none_match_wins_over_if_modified_since() {
+        let last_modified = Utc.with_ymd_and_hms(2026, 10, 1, 0, 0, 0).unwrap();
+        let feed = rendered(Some(last_modified.into()));
+        let req = TestRequest::default()
+            .insert_header((header::IF_NONE_MATCH, "\"xyz\""))
+            .insert_header((header::IF_MODIFIED_SINCE, http_date(last_modified)))
+            .to_http_request();
+        assert!(!feed.is_not_modified(&req));
+    }
+
+    #[test]
+    fn if_modified_since_compares_with_last_modified() {
+        let last_modified = Utc.with_ymd_and_hms(2026, 10, 1, 0, 0, 0).unwrap();
+        let feed = rendered(Some(last_modified.into()));
+        let since = |d: DateTime<Utc>| {
+            TestRequest::default()
+                .insert_header((header::IF_MODIFIED_SINCE, http_date(d)))
+                .to_http_request()
+        };
+        assert!(feed.is_not_modified(&since(last_modified)));
+        assert!(feed.is_not_modified(&since(last_modified + Duration::hours(1))));
+        assert!(!feed.is_not_modified(&since(last_modified - Duration::hours(1))));
+        assert!(!rendered(None).is_not_modified(&since(last_modified)));
+    }
+}
@@ -0,0 +1,46 @@
+use super::{Feed, FEED_TTL_MINUTES};
+use chrono::{DateTime, Utc};
+use rss::{Channel, ChannelBuilder, GuidBuilder, Image, ImageBuilder, Item, ItemBuilder};
+
+const RSS_2_SPECIFICATION_URL: &str = "https://validator.w3.org/feed/docs/rss2.html";
+
+pub fn to_channel(feed: &Feed, now: DateTime<Utc>) -> Channel {
+    // RSS 2.0 dates must follow RFC 822, which RFC 2822 supersedes.
+    let newest_item_date = feed.updated(now).map(|d| d.to_rfc2822());
+
+    let rss_image: Image = ImageBuilder::default()
+        .title(&feed.title)
+        .link(&feed.link)
+        .url(&feed.image_url)
+        .build();
+
+    let items: Vec<Item> = feed
+        .items
+        .iter()
+        .map(|item| {
+            let guid = GuidBuilder::default()
+                .value(item.guid.clone())
+                .permalink(false)
+                .build();
+            ItemBuilder::default()
+                .guid(Some(guid))
+                .title(Some(item.title.clone()))
+                .link(item.link.clone())
+                .description(item.description.clone())
+                .pub_date(Some(item.published.to_rfc2822()))
+                .build()
+        })
+        .collect();
+
+    ChannelBuilder::default()
+        .title(&feed.title)
+        .link(&feed.link)
+        .description(&feed.description)
+        .image(rss_image)
+        .docs(RSS_2_SPECIFICATION_URL.to_string())
+        .items(items)
+        .pub_date(newest_item_date.clone())
+        .last_build_date(newest_item_date)
+        .ttl(Some(FEED_TTL_MINUTES.to_string()))
+        .build()
+}
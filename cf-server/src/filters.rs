@@ -0,0 +1,234 @@
+use crate::{errors, errors::ServiceError};
+use regex::{Regex, RegexBuilder};
+
+const REGEX_SIZE_LIMIT: usize = 1 << 16;
+const MEDIA_TYPES: [&str; 2] = ["ANIME", "MANGA"];
+const MEDIA_FORMATS: [&str; 10] = [
+    "TV", "TV_SHORT", "MOVIE", "SPECIAL", "OVA", "ONA", "MUSIC", "MANGA", "NOVEL", "ONE_SHOT",
+];
+const MEDIA_STATUSES: [&str; 5] = [
+    "FINISHED",
+    "RELEASING",
+    "NOT_YET_RELEASED",
+    "CANCELLED",
+    "HIATUS",
+];
+
+/// Decides which `staffRole`s make it into a feed. Substrings and regexes are
+/// both matched case-insensitively. A role is kept when it matches any include
+/// rule, or there are none, and matches no exclude rule.
+#[derive(Debug)]
+pub struct RoleFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+    include_regex: Option<Regex>,
+    exclude_regex: Option<Regex>,
+}
+
+impl RoleFilter {
+    pub fn new(
+        include: Option<&str>,
+        exclude: Option<&str>,
+        include_regex: Option<&str>,
+        exclude_regex: Option<&str>,
+    ) -> Result<RoleFilter, ServiceError> {
+        Ok(RoleFilter {
+            include: split_substrings(include),
+            exclude: split_substrings(exclude),
+            include_regex: include_regex
+                .map(|r| build_regex("include_role_regex", r))
+                .transpose()?,
+            exclude_regex: exclude_regex
+                .map(|r| build_regex("exclude_role_regex", r))
+                .transpose()?,
+        })
+    }
+
+    pub fn matches(&self, role: &str) -> bool {
+        let lowercase_role = role.to_lowercase();
+        let has_include_rules = !self.include.is_empty() || self.include_regex.is_some();
+        let included = !has_include_rules
+            || self.include.iter().any(|s| lowercase_role.contains(s))
+            || self
+                .include_regex
+                .as_ref()
+                .is_some_and(|r| r.is_match(role));
+        let excluded = self.exclude.iter().any(|s| lowercase_role.contains(s))
+            || self
+                .exclude_regex
+                .as_ref()
+                .is_some_and(|r| r.is_match(role));
+
+        included && !excluded
+    }
+}
+
+/// Keeps media whose Anilist `type`, `format` and `status` are in the allowed
+/// lists. An empty list allows everything, including media missing that field.
+#[derive(Debug)]
+pub struct MediaFilter {
+    media_types: Vec<String>,
+    formats: Vec<String>,
+    statuses: Vec<String>,
+}
+
+impl MediaFilter {
+    pub fn new(
+        media_type: Option<&str>,
+        format: Option<&str>,
+        status: Option<&str>,
+    ) -> Result<MediaFilter, ServiceError> {
+        Ok(MediaFilter {
+            media_types: parse_enum_list("media_type", media_type, &MEDIA_TYPES)?,
+            formats: parse_enum_list("format", format, &MEDIA_FORMATS)?,
+            statuses: parse_enum_list("status", status, &MEDIA_STATUSES)?,
+        })
+    }
+
+    pub fn matches(
+        &self,
+        media_type: Option<&str>,
+        format: Option<&str>,
+        status: Option<&str>,
+    ) -> bool {
+        allows(&self.media_types, media_type)
+            && allows(&self.formats, format)
+            && allows(&self.statuses, status)
+    }
+}
+
+fn allows(allowed: &[String], value: Option<&str>) -> bool {
+    allowed.is_empty() || value.is_some_and(|v| allowed.iter().any(|a| a == v))
+}
+
+/// Comma separated Anilist enum values, e.g. "not_yet_released,RELEASING".
+fn parse_enum_list(
+    parameter: &'static str,
+    value: Option<&str>,
+    known_values: &[&str],
+) -> Result<Vec<String>, ServiceError> {
+    split_substrings(value)
+        .into_iter()
+        .map(|v| v.to_uppercase())
+        .map(|v| {
+            if known_values.contains(&v.as_str()) {
+                Ok(v)
+            } else {
+                Err(errors::invalid_parameter(
+                    parameter,
+                    format!("must only list {}", known_values.join(", ")),
+                ))
+            }
+        })
+        .collect()
+}
+
+/// Comma separated, case-insensitive substrings, e.g. "director,series composition".
+fn split_substrings(value: Option<&str>) -> Vec<String> {
+    value
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_lowercase())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Like other invalid values, bad patterns aren't quoted back in the error.
+fn build_regex(parameter: &'static str, pattern: &str) -> Result<Regex, ServiceError> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .build()
+        .map_err(|_| {
+            errors::invalid_parameter(parameter, "must be a valid, small regex".to_string())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{body, http::header, http::StatusCode, ResponseError};
+
+    fn role_filter(
+        include: Option<&str>,
+        exclude: Option<&str>,
+        include_regex: Option<&str>,
+        exclude_regex: Option<&str>,
+    ) -> RoleFilter {
+        RoleFilter::new(include, exclude, include_regex, exclude_regex).unwrap()
+    }
+
+    #[test]
+    fn role_filter_without_rules_keeps_every_role() {
+        let filter = role_filter(None, None, None, None);
+        assert!(filter.matches("Director"));
+        assert!(filter.matches(""));
+    }
+
+    #[test]
+    fn role_filter_include_keeps_only_matching_roles() {
+        let filter = role_filter(Some("director, storyboard"), None, None, None);
+        assert!(filter.matches("Episode Director (ep 3)"));
+        assert!(filter.matches("Storyboard (OP)"));
+        assert!(!filter.matches("Key Animation"));
+    }
+
+    #[test]
+    fn role_filter_exclude_wins_over_include() {
+        let filter = role_filter(Some("director"), Some("assistant"), None, None);
+        assert!(filter.matches("Director"));
+        assert!(!filter.matches("Assistant Director"));
+
+        let filter = role_filter(None, None, Some("director"), Some("^assistant"));
+        assert!(!filter.matches("Assistant Director"));
+    }
+
+    #[test]
+    fn role_filter_is_case_insensitive() {
+        let filter = role_filter(Some("DIRECTOR"), Some("Unit"), None, None);
+        assert!(filter.matches("director"));
+        assert!(!filter.matches("2nd UNIT Director"));
+
+        let filter = role_filter(None, None, Some("^key anim"), None);
+        assert!(filter.matches("Key Animation (ep 7)"));
+    }
+
+    #[test]
+    fn role_filter_includes_on_substring_or_regex() {
+        let filter = role_filter(Some("storyboard"), None, Some("^director$"), None);
+        assert!(filter.matches("Director"));
+        assert!(filter.matches("Storyboard"));
+        assert!(!filter.matches("Episode Director"));
+    }
+
+    #[actix_web::test]
+    async fn invalid_regex_is_a_plain_text_400() {
+        let pattern = "<img src=x onerror=alert(1)>(";
+        let error = RoleFilter::new(None, None, Some(pattern), None).unwrap_err();
+        assert!(matches!(error, ServiceError::InvalidParameter(_)));
+
+        let response = error.error_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/plain; charset=utf-8"
+        );
+        let body = body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&body),
+            "Invalid query parameter include_role_regex: must be a valid, small regex"
+        );
+    }
+
+    #[test]
+    fn unknown_media_values_are_not_repeated() {
+        let error = MediaFilter::new(None, Some("tv,<b>bogus</b>"), None).unwrap_err();
+        match error {
+            ServiceError::InvalidParameter(e) => {
+                assert_eq!(e.parameter, "format");
+                assert!(!e.message.to_lowercase().contains("bogus"));
+            }
+            e => panic!("expected an invalid parameter, got {:?}", e),
+        }
+    }
+}
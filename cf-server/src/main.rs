@@ -1,40 +1,101 @@
-use actix_files;
-use actix_web::{get, middleware, web, App, Error, HttpRequest, HttpServer};
-use clients::AnilistClient;
-use reqwest;
+use actix_web::{middleware, web, App, HttpServer};
+use cache::AnilistCache;
+use clients::{AnilistClient, RetryPolicy};
+use feeds::RenderedFeed;
+use rate_limiter::AnilistRateLimiter;
+use refresher::{RefreshConfig, RefreshRegistry, Refresher};
+use repository::AnilistRepository;
+use single_flight::SingleFlight;
+use storage::Storage;
 
 #[macro_use]
 extern crate log;
 
+mod anilist_utils;
+mod bundles;
+mod cache;
 mod clients;
 mod errors;
+mod feeds;
+mod filters;
+mod models;
+mod opml;
+mod rate_limiter;
+mod refresher;
+mod repository;
 mod routes;
+mod single_flight;
+mod storage;
 
 const STATIC_JS_PATH: &str = "static/js";
 const STATIC_CSS_PATH: &str = "static/css";
+const DATABASE_URL_ENV: &str = "DATABASE_URL";
+const DEFAULT_DATABASE_URL: &str = "sqlite://creator-follower.db";
+const PUBLIC_URL_ENV: &str = "PUBLIC_URL";
 
 struct AppState {
-    anilist_client: AnilistClient,
+    anilist_repository: AnilistRepository,
+    storage: Storage,
+    feed_builds: SingleFlight<RenderedFeed>,
+    /// Scheme and host links back to this server are built from, e.g.
+    /// "https://creatorfollower.com". Taken from each request when unset.
+    public_url: Option<String>,
 }
 
 impl AppState {
-    fn new() -> Self {
-        AppState {
-            anilist_client: AnilistClient {
-                client: reqwest::Client::new(),
+    async fn new(refresh_config: RefreshConfig) -> std::io::Result<Self> {
+        let database_url =
+            std::env::var(DATABASE_URL_ENV).unwrap_or_else(|_| DEFAULT_DATABASE_URL.to_string());
+        let storage = Storage::connect(&database_url)
+            .await
+            .map_err(std::io::Error::other)?;
+
+        Ok(AppState {
+            anilist_repository: AnilistRepository {
+                client: AnilistClient {
+                    client: clients::http_client_from_env().map_err(std::io::Error::other)?,
+                    cache: AnilistCache::from_env(),
+                    rate_limiter: AnilistRateLimiter::from_env(),
+                    retry_policy: RetryPolicy::from_env(),
+                },
+                storage: storage.clone(),
+                refresh_registry: RefreshRegistry::new(refresh_config),
+                stored_fresh_for: stored_fresh_for(&refresh_config),
             },
-        }
+            storage,
+            feed_builds: SingleFlight::new(),
+            public_url: std::env::var(PUBLIC_URL_ENV)
+                .ok()
+                .map(|url| url.trim_end_matches('/').to_string())
+                .filter(|url| !url.is_empty()),
+        })
     }
 }
 
+/// Stored staff stay fresh through one missed refresh, e.g. when the refresh
+/// budget runs short. Without background refreshes every request asks Anilist.
+fn stored_fresh_for(refresh_config: &RefreshConfig) -> chrono::Duration {
+    if !refresh_config.is_enabled() {
+        return chrono::Duration::zero();
+    }
+    chrono::Duration::from_std(refresh_config.interval * 2).unwrap_or(chrono::Duration::zero())
+}
+
 type AppData = web::Data<AppState>;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    std::env::set_var("RUST_LOG", "actix_web=info");
+    dotenvy::dotenv().ok();
+    std::env::set_var("RUST_LOG", "actix_web=info,cf_server=info");
     pretty_env_logger::init();
 
-    let application_state = AppState::new();
+    let refresh_config = RefreshConfig::from_env();
+    let application_state = AppState::new(refresh_config).await?;
+    if refresh_config.is_enabled() {
+        actix_web::rt::spawn(
+            Refresher::new(application_state.anilist_repository.clone(), refresh_config).run(),
+        );
+    }
     let data = web::Data::new(application_state);
     info!("Starting server on: http://0.0.0.0:8080");
     HttpServer::new(move || {
@@ -43,6 +104,8 @@ async fn main() -> std::io::Result<()> {
             .app_data(data.clone())
             .configure(routes::init_health_routes)
             .configure(routes::init_anilist_routes)
+            .configure(routes::init_bundle_routes)
+            .configure(routes::init_opml_routes)
             .configure(routes::init_page_routes)
             .service(actix_files::Files::new(STATIC_JS_PATH, STATIC_JS_PATH).show_files_listing())
             .service(actix_files::Files::new(STATIC_CSS_PATH, STATIC_CSS_PATH).show_files_listing())
@@ -0,0 +1,216 @@
+use chrono::{DateTime, Utc};
+
+/// Source independent copies of the Anilist data feeds are built from. These are
+/// what `storage` persists, so they only hold owned, plain values.
+#[derive(Clone, Debug)]
+pub struct Staff {
+    pub id: i64,
+    pub name_full: Option<String>,
+    pub name_native: Option<String>,
+    pub site_url: Option<String>,
+    pub description: Option<String>,
+    pub image_large: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FuzzyDate {
+    pub year: Option<i64>,
+    pub month: Option<i64>,
+    pub day: Option<i64>,
+}
+
+impl FuzzyDate {
+    /// The known part of the date, e.g. "2024", "2024-04" or "2024-04-05".
+    pub fn to_iso(&self) -> Option<String> {
+        match (self.year, self.month, self.day) {
+            (Some(y), Some(m), Some(d)) => Some(format!("{:04}-{:02}-{:02}", y, m, d)),
+            (Some(y), Some(m), None) => Some(format!("{:04}-{:02}", y, m)),
+            (Some(y), None, _) => Some(format!("{:04}", y)),
+            (None, _, _) => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Media {
+    pub id: i64,
+    pub title_romaji: Option<String>,
+    pub title_english: Option<String>,
+    pub title_native: Option<String>,
+    pub media_type: Option<String>,
+    pub format: Option<String>,
+    pub status: Option<String>,
+    pub description: Option<String>,
+    pub site_url: Option<String>,
+    pub start_date: FuzzyDate,
+}
+
+#[derive(Clone, Debug)]
+pub struct StaffCredit {
+    pub role: String,
+    pub media: Media,
+    /// When the credit was first stored, `None` until it has been saved.
+    pub first_seen_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Character {
+    pub id: i64,
+    pub name_full: Option<String>,
+    pub name_native: Option<String>,
+    pub image_large: Option<String>,
+    pub site_url: Option<String>,
+}
+
+/// A character a staff voiced on a media.
+#[derive(Clone, Debug)]
+pub struct CharacterCredit {
+    pub character: Character,
+    /// Anilist `CharacterRole` name, e.g. "MAIN".
+    pub character_role: Option<String>,
+    pub media: Media,
+    /// See `MediaCredit`.
+    pub first_seen_at: Option<DateTime<Utc>>,
+}
+
+/// An episode of an airing anime that has already aired.
+#[derive(Clone, Debug)]
+pub struct AiredEpisode {
+    pub media_id: i64,
+    pub episode: i64,
+    pub aired_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug)]
+pub struct VoiceActor {
+    pub id: i64,
+    pub name_full: Option<String>,
+    pub name_native: Option<String>,
+    /// Primary language, e.g. "Japanese".
+    pub language: Option<String>,
+}
+
+/// A media a character appears in, along with who voiced them there.
+#[derive(Clone, Debug)]
+pub struct CharacterAppearance {
+    /// Anilist `CharacterRole` name, e.g. "MAIN".
+    pub character_role: Option<String>,
+    pub voice_actors: Vec<VoiceActor>,
+    pub media: Media,
+    /// See `MediaCredit`.
+    pub first_seen_at: Option<DateTime<Utc>>,
+}
+
+/// A character along with every media Anilist lists them in, in Anilist's
+/// order.
+#[derive(Clone, Debug)]
+pub struct CharacterAppearances {
+    pub character: Character,
+    pub description: Option<String>,
+    pub appearances: Vec<CharacterAppearance>,
+}
+
+/// A staff along with every credit Anilist lists for them, in Anilist's order.
+#[derive(Clone, Debug)]
+pub struct StaffFilmography {
+    pub staff: Staff,
+    pub credits: Vec<StaffCredit>,
+    /// When the staff was first stored, `None` until it has been saved.
+    pub first_fetched_at: Option<DateTime<Utc>>,
+    /// When Anilist sent this copy: its oldest page for a fetched filmography,
+    /// the last save for a stored one.
+    pub fetched_at: Option<DateTime<Utc>>,
+    /// Status and start date changes seen on the credited media, oldest first.
+    pub media_changes: Vec<MediaChange>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Studio {
+    pub id: i64,
+    pub name: String,
+    pub site_url: Option<String>,
+    pub is_animation_studio: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct StudioCredit {
+    /// Whether the studio animated the media rather than, say, producing it.
+    pub is_main: bool,
+    pub media: Media,
+    /// See `MediaCredit`.
+    pub first_seen_at: Option<DateTime<Utc>>,
+}
+
+/// A studio along with every media Anilist lists for it, in Anilist's order.
+/// Studios aren't stored, so there are no changes.
+#[derive(Clone, Debug)]
+pub struct StudioFilmography {
+    pub studio: Studio,
+    pub credits: Vec<StudioCredit>,
+}
+
+/// A credit on a single media that isn't stored. Feeds date media without a
+/// start year by when they were first seen, so `first_seen_at` is only looked
+/// up for those and stays `None` for the rest.
+pub trait MediaCredit {
+    fn media(&self) -> &Media;
+    fn first_seen_at(&self) -> Option<DateTime<Utc>>;
+    fn set_first_seen_at(&mut self, first_seen_at: Option<DateTime<Utc>>);
+}
+
+macro_rules! impl_media_credit {
+    ($credit:ty) => {
+        impl MediaCredit for $credit {
+            fn media(&self) -> &Media {
+                &self.media
+            }
+
+            fn first_seen_at(&self) -> Option<DateTime<Utc>> {
+                self.first_seen_at
+            }
+
+            fn set_first_seen_at(&mut self, first_seen_at: Option<DateTime<Utc>>) {
+                self.first_seen_at = first_seen_at;
+            }
+        }
+    };
+}
+
+impl_media_credit!(CharacterCredit);
+impl_media_credit!(CharacterAppearance);
+impl_media_credit!(StudioCredit);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MediaChangeKind {
+    Status,
+    StartDate,
+}
+
+/// A change to a stored media noticed on a later fetch. Values are Anilist
+/// `MediaStatus` names or `FuzzyDate::to_iso` dates, `None` when unset.
+#[derive(Clone, Debug)]
+pub struct MediaChange {
+    pub id: i64,
+    pub media_id: i64,
+    pub kind: MediaChangeKind,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub changed_at: DateTime<Utc>,
+}
+
+impl MediaChangeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MediaChangeKind::Status => "status",
+            MediaChangeKind::StartDate => "start_date",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<MediaChangeKind> {
+        match name {
+            "status" => Some(MediaChangeKind::Status),
+            "start_date" => Some(MediaChangeKind::StartDate),
+            _ => None,
+        }
+    }
+}
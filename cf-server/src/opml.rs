@@ -0,0 +1,147 @@
+use crate::{errors, errors::ServiceError};
+use chrono::Utc;
+use quick_xml::{
+    events::{BytesDecl, BytesText, Event},
+    Reader, Writer,
+};
+use regex::Regex;
+
+/// Matches the staff feed URLs this site hands out, in any format.
+const STAFF_FEED_URL_PATTERN: &str = r"/(?:rss|atom|json)/anilist/staff/(\d+)(?:[/?#]|$)";
+/// The XML parser's own messages quote the file back, so they aren't sent.
+const NOT_OPML: &str = "must be a well-formed OPML file";
+
+/// One subscription in an OPML file.
+pub struct OpmlOutline {
+    pub title: String,
+    pub xml_url: String,
+    pub html_url: String,
+}
+
+/// Renders an OPML 2.0 subscription list, one outline per feed.
+pub fn to_opml(title: &str, outlines: &[OpmlOutline]) -> Result<String, ServiceError> {
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+    let date_created = Utc::now().to_rfc2822();
+    writer
+        .write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))
+        .map_err(|_| ServiceError::InternalError)?;
+    writer
+        .create_element("opml")
+        .with_attribute(("version", "2.0"))
+        .write_inner_content(|w| {
+            w.create_element("head").write_inner_content(|w| {
+                w.create_element("title")
+                    .write_text_content(BytesText::new(title))?;
+                w.create_element("dateCreated")
+                    .write_text_content(BytesText::new(&date_created))?;
+                Ok(())
+            })?;
+            w.create_element("body").write_inner_content(|w| {
+                for outline in outlines {
+                    w.create_element("outline")
+                        .with_attributes([
+                            ("type", "rss"),
+                            ("text", outline.title.as_str()),
+                            ("title", outline.title.as_str()),
+                            ("xmlUrl", outline.xml_url.as_str()),
+                            ("htmlUrl", outline.html_url.as_str()),
+                        ])
+                        .write_empty()?;
+                }
+                Ok(())
+            })?;
+            Ok(())
+        })
+        .map_err(|_| ServiceError::InternalError)?;
+
+    String::from_utf8(writer.into_inner()).map_err(|_| ServiceError::InternalError)
+}
+
+/// Staff ids of every staff feed subscribed to in an OPML file, in file order
+/// and without repeats. Outlines for any other feed are skipped.
+pub fn staff_ids_from_opml(opml: &str) -> Result<Vec<i64>, ServiceError> {
+    let staff_feed_url = Regex::new(STAFF_FEED_URL_PATTERN)
+        .map_err(|_| errors::internal_logic_error("Staff feed URL pattern is a valid regex"))?;
+    let mut reader = Reader::from_str(opml);
+    let mut staff_ids: Vec<i64> = Vec::new();
+    loop {
+        let outline = match reader.read_event() {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) if e.name().as_ref() == b"outline" => e,
+            Ok(Event::Eof) => break,
+            Ok(_) => continue,
+            Err(_) => return Err(errors::invalid_parameter("opml", NOT_OPML.to_string())),
+        };
+        for attribute in outline.attributes().flatten() {
+            if attribute.key.as_ref() != b"xmlUrl" {
+                continue;
+            }
+            let xml_url = attribute
+                .decode_and_unescape_value(reader.decoder())
+                .map_err(|_| errors::invalid_parameter("opml", NOT_OPML.to_string()))?;
+            let staff_id = staff_feed_url
+                .captures(&xml_url)
+                .and_then(|c| c[1].parse::<i64>().ok());
+            if let Some(id) = staff_id.filter(|id| !staff_ids.contains(id)) {
+                staff_ids.push(id);
+            }
+        }
+    }
+    Ok(staff_ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opml(outlines: &str) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<opml version="2.0"><head><title>Feeds</title></head><body>{}</body></opml>"#,
+            outlines
+        )
+    }
+
+    #[test]
+    fn staff_feeds_in_every_format_are_found() {
+        let opml = opml(
+            r#"<outline type="rss" xmlUrl="https://example.com/rss/anilist/staff/101"/>
+            <outline type="rss" xmlUrl="https://example.com/atom/anilist/staff/102"/>
+            <outline type="rss" xmlUrl="http://localhost:8080/json/anilist/staff/103"/>"#,
+        );
+        assert_eq!(staff_ids_from_opml(&opml).unwrap(), vec![101, 102, 103]);
+    }
+
+    #[test]
+    fn query_strings_and_fragments_are_ignored() {
+        let opml = opml(
+            r#"<outline xmlUrl="https://example.com/rss/anilist/staff/101?include_role=Director&amp;media_type=ANIME"/>
+            <outline xmlUrl="https://example.com/atom/anilist/staff/102#top"/>
+            <outline xmlUrl="https://example.com/rss/anilist/staff/103/"/>"#,
+        );
+        assert_eq!(staff_ids_from_opml(&opml).unwrap(), vec![101, 102, 103]);
+    }
+
+    #[test]
+    fn other_feeds_and_repeats_are_skipped() {
+        let opml = opml(
+            r#"<outline text="Folder">
+                <outline xmlUrl="https://example.com/rss/anilist/staff/101"/>
+                <outline xmlUrl="https://example.com/rss/anilist/studio/5"/>
+                <outline xmlUrl="https://example.com/rss/anilist/staff?ids=1,2"/>
+                <outline xmlUrl="https://example.com/rss/anilist/staff/1010x"/>
+                <outline xmlUrl="https://blog.example.com/feed.xml"/>
+            </outline>
+            <outline xmlUrl="https://example.com/json/anilist/staff/101"/>"#,
+        );
+        assert_eq!(staff_ids_from_opml(&opml).unwrap(), vec![101]);
+    }
+
+    #[test]
+    fn broken_xml_is_an_invalid_parameter() {
+        let error = staff_ids_from_opml("<opml><body><outline xmlUrl=\"a></body>").unwrap_err();
+        match error {
+            ServiceError::InvalidParameter(e) => assert_eq!(e.message, NOT_OPML),
+            e => panic!("expected an invalid parameter, got {:?}", e),
+        }
+    }
+}
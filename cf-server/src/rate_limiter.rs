@@ -0,0 +1,235 @@
+use crate::{cache::env_u64, errors, errors::ServiceError};
+use actix_web::rt::time;
+use reqwest::header::HeaderMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const MAX_WAIT_ENV: &str = "ANILIST_RATE_LIMIT_MAX_WAIT_SECONDS";
+const DEFAULT_MAX_WAIT_SECONDS: u64 = 30;
+const RATE_LIMIT_REMAINING_HEADER: &str = "x-ratelimit-remaining";
+const RATE_LIMIT_RESET_HEADER: &str = "x-ratelimit-reset";
+const RETRY_AFTER_HEADER: &str = "retry-after";
+/// Anilist's limit is per minute, used when a response doesn't say when the
+/// current window resets.
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+/// Once fewer requests than this are left in the window, the rest are spread
+/// evenly over what is left of it rather than sent at once.
+const RATE_LIMIT_RESERVE: u64 = 10;
+
+#[derive(Default)]
+struct RateLimitState {
+    remaining: Option<u64>,
+    reset_at: Option<Instant>,
+    retry_at: Option<Instant>,
+    next_request_at: Option<Instant>,
+}
+
+/// Client side view of Anilist's rate limit, built from the headers of every
+/// response. Requests wait their turn here instead of running into 429s.
+/// Clones share the same state.
+#[derive(Clone)]
+pub struct AnilistRateLimiter {
+    pub max_wait: Duration,
+    state: Arc<Mutex<RateLimitState>>,
+}
+
+impl AnilistRateLimiter {
+    /// Reads how long a request may wait on the rate limit from the
+    /// environment, falling back to the default above.
+    pub fn from_env() -> Self {
+        let max_wait = env_u64(MAX_WAIT_ENV, DEFAULT_MAX_WAIT_SECONDS);
+        info!("Anilist rate limit: max_wait={}s", max_wait);
+        AnilistRateLimiter {
+            max_wait: Duration::from_secs(max_wait),
+            state: Arc::new(Mutex::new(RateLimitState::default())),
+        }
+    }
+
+    /// Waits until a request may be sent, or fails right away with
+    /// `ServiceError::AnilistRateLimited` when that is after `deadline`.
+    pub async fn acquire(&self, deadline: Instant) -> Result<(), ServiceError> {
+        let now = Instant::now();
+        let slot = self
+            .reserve(now, deadline)
+            .map_err(|slot| errors::anilist_rate_limited(slot - now))?;
+        if slot > now {
+            info!(
+                "Waiting {}ms on the Anilist rate limit",
+                (slot - now).as_millis()
+            );
+            time::sleep(slot - now).await;
+        }
+        Ok(())
+    }
+
+    /// Records the rate limit headers of a response, returning its
+    /// `Retry-After` if it had one.
+    pub fn update(&self, headers: &HeaderMap) -> Option<Duration> {
+        let now = Instant::now();
+        let remaining = header_u64(headers, RATE_LIMIT_REMAINING_HEADER);
+        let reset_at = header_u64(headers, RATE_LIMIT_RESET_HEADER).map(|reset| {
+            let unix_now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            now + Duration::from_secs(reset.saturating_sub(unix_now))
+        });
+        let retry_after = header_u64(headers, RETRY_AFTER_HEADER).map(Duration::from_secs);
+
+        let mut state = self.state.lock().expect("Rate limit lock is poisoned");
+        if remaining.is_some() {
+            state.remaining = remaining;
+        }
+        if let Some(retry_after) = retry_after {
+            state.retry_at = Some(now + retry_after);
+        }
+        // A 429 without a reset time is over once its Retry-After has passed.
+        match (reset_at, state.retry_at) {
+            (Some(reset_at), _) => state.reset_at = Some(reset_at),
+            (None, Some(retry_at)) if retry_after.is_some() => state.reset_at = Some(retry_at),
+            _ => {}
+        }
+        retry_after
+    }
+
+    /// Books the earliest time the next request may be sent. When that is after
+    /// `deadline` nothing is booked, so a request that gives up doesn't push
+    /// back the ones after it, and the time is returned as the error.
+    fn reserve(&self, now: Instant, deadline: Instant) -> Result<Instant, Instant> {
+        let mut state = self.state.lock().expect("Rate limit lock is poisoned");
+        if state.reset_at.is_some_and(|reset_at| reset_at <= now) {
+            state.remaining = None;
+            state.reset_at = None;
+        }
+        let slot = [state.retry_at, state.next_request_at]
+            .into_iter()
+            .flatten()
+            .fold(now, Instant::max);
+        let reset_at = state.reset_at.unwrap_or(now + RATE_LIMIT_WINDOW);
+        let slot = match state.remaining {
+            Some(0) => slot.max(reset_at),
+            _ => slot,
+        };
+        if slot > deadline {
+            return Err(slot);
+        }
+
+        match state.remaining {
+            Some(0) => {
+                state.remaining = None;
+                state.reset_at = None;
+                state.next_request_at = Some(slot);
+            }
+            Some(remaining) if remaining < RATE_LIMIT_RESERVE => {
+                let spacing = reset_at.saturating_duration_since(now) / remaining as u32;
+                state.remaining = Some(remaining - 1);
+                state.next_request_at = Some(slot + spacing);
+            }
+            Some(remaining) => state.remaining = Some(remaining - 1),
+            None => {}
+        }
+        Ok(slot)
+    }
+}
+
+fn header_u64(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn rate_limiter(state: RateLimitState) -> AnilistRateLimiter {
+        AnilistRateLimiter {
+            max_wait: Duration::from_secs(DEFAULT_MAX_WAIT_SECONDS),
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    #[test]
+    fn requests_go_straight_through_with_plenty_remaining() {
+        let now = Instant::now();
+        let limiter = rate_limiter(RateLimitState {
+            remaining: Some(60),
+            reset_at: Some(now + RATE_LIMIT_WINDOW),
+            ..RateLimitState::default()
+        });
+        assert_eq!(limiter.reserve(now, now), Ok(now));
+        assert_eq!(limiter.reserve(now, now), Ok(now));
+    }
+
+    #[test]
+    fn last_requests_are_spread_over_the_window() {
+        let now = Instant::now();
+        let limiter = rate_limiter(RateLimitState {
+            remaining: Some(5),
+            reset_at: Some(now + Duration::from_secs(50)),
+            ..RateLimitState::default()
+        });
+        let deadline = now + Duration::from_secs(60);
+        assert_eq!(limiter.reserve(now, deadline), Ok(now));
+        assert_eq!(
+            limiter.reserve(now, deadline),
+            Ok(now + Duration::from_secs(10))
+        );
+    }
+
+    #[test]
+    fn exhausted_window_waits_for_the_reset() {
+        let now = Instant::now();
+        let reset_at = now + Duration::from_secs(30);
+        let limiter = rate_limiter(RateLimitState {
+            remaining: Some(0),
+            reset_at: Some(reset_at),
+            ..RateLimitState::default()
+        });
+        assert_eq!(limiter.reserve(now, reset_at), Ok(reset_at));
+    }
+
+    #[test]
+    fn giving_up_books_nothing() {
+        let now = Instant::now();
+        let limiter = rate_limiter(RateLimitState {
+            remaining: Some(5),
+            reset_at: Some(now + Duration::from_secs(50)),
+            ..RateLimitState::default()
+        });
+        let second_slot = now + Duration::from_secs(10);
+        assert_eq!(limiter.reserve(now, now), Ok(now));
+        assert_eq!(limiter.reserve(now, now), Err(second_slot));
+        assert_eq!(limiter.reserve(now, now), Err(second_slot));
+        assert_eq!(
+            limiter.reserve(now, now + RATE_LIMIT_WINDOW),
+            Ok(second_slot)
+        );
+    }
+
+    #[test]
+    fn retry_after_holds_back_requests() {
+        let limiter = rate_limiter(RateLimitState::default());
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER_HEADER, HeaderValue::from_static("5"));
+        assert_eq!(limiter.update(&headers), Some(Duration::from_secs(5)));
+
+        let now = Instant::now();
+        let slot = limiter.reserve(now, now + Duration::from_secs(10)).unwrap();
+        assert!(slot > now + Duration::from_secs(4));
+        assert!(slot <= now + Duration::from_secs(5));
+        assert!(limiter.reserve(now, now + Duration::from_secs(1)).is_err());
+    }
+
+    #[test]
+    fn retry_after_ends_the_window_without_a_reset_header() {
+        let limiter = rate_limiter(RateLimitState::default());
+        let mut headers = HeaderMap::new();
+        headers.insert(RATE_LIMIT_REMAINING_HEADER, HeaderValue::from_static("0"));
+        headers.insert(RETRY_AFTER_HEADER, HeaderValue::from_static("5"));
+        limiter.update(&headers);
+
+        let now = Instant::now();
+        let slot = limiter.reserve(now, now + Duration::from_secs(10)).unwrap();
+        assert!(slot <= now + Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,193 @@
+use crate::{anilist_utils, cache::env_u64, repository::AnilistRepository};
+use actix_web::rt::time;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const REFRESH_INTERVAL_ENV: &str = "ANILIST_REFRESH_INTERVAL_SECONDS";
+const REFRESH_IDLE_ENV: &str = "ANILIST_REFRESH_IDLE_SECONDS";
+const REFRESH_REQUESTS_PER_MINUTE_ENV: &str = "ANILIST_REFRESH_REQUESTS_PER_MINUTE";
+const DEFAULT_REFRESH_INTERVAL_SECONDS: u64 = 30 * 60;
+const DEFAULT_REFRESH_IDLE_SECONDS: u64 = 7 * 24 * 60 * 60;
+const DEFAULT_REFRESH_REQUESTS_PER_MINUTE: u64 = 20;
+const REFRESH_TICK: Duration = Duration::from_secs(5);
+/// Refreshes are spread up to this fraction of the interval either side of
+/// their due time so staff first requested together don't stay in lockstep.
+const REFRESH_JITTER: f64 = 0.1;
+
+#[derive(Clone, Copy, Debug)]
+pub struct RefreshConfig {
+    pub interval: Duration,
+    /// Staff that haven't been requested for this long stop being refreshed.
+    pub idle_after: Duration,
+    /// Anilist requests all background refreshes together may make per minute.
+    /// Feed requests don't count against it, they only share Anilist's rate
+    /// limit with the refresher.
+    pub requests_per_minute: u64,
+}
+
+impl RefreshConfig {
+    /// Reads the refresh settings from the environment, falling back to the
+    /// defaults above. An interval of 0 turns background refreshes off.
+    pub fn from_env() -> Self {
+        let config = RefreshConfig {
+            interval: Duration::from_secs(env_u64(
+                REFRESH_INTERVAL_ENV,
+                DEFAULT_REFRESH_INTERVAL_SECONDS,
+            )),
+            idle_after: Duration::from_secs(env_u64(
+                REFRESH_IDLE_ENV,
+                DEFAULT_REFRESH_IDLE_SECONDS,
+            )),
+            requests_per_minute: env_u64(
+                REFRESH_REQUESTS_PER_MINUTE_ENV,
+                DEFAULT_REFRESH_REQUESTS_PER_MINUTE,
+            ),
+        };
+        info!(
+            "Anilist refresh: interval={}s, idle_after={}s, requests_per_minute={}",
+            config.interval.as_secs(),
+            config.idle_after.as_secs(),
+            config.requests_per_minute
+        );
+        config
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.interval.is_zero() && self.requests_per_minute > 0
+    }
+}
+
+struct RefreshEntry {
+    last_requested_at: Instant,
+    next_refresh_at: Instant,
+    /// Anilist requests the last fetch of the staff took.
+    page_count: u32,
+}
+
+/// Staff ids readers recently asked feeds for, along with when each is due for
+/// a background refresh. Clones share the same registry.
+#[derive(Clone)]
+pub struct RefreshRegistry {
+    config: RefreshConfig,
+    staff: Arc<Mutex<HashMap<i64, RefreshEntry>>>,
+}
+
+impl RefreshRegistry {
+    pub fn new(config: RefreshConfig) -> Self {
+        RefreshRegistry {
+            config,
+            staff: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Keeps `staff_id` refreshed until it goes unrequested for `idle_after`.
+    pub fn record_request(&self, staff_id: i64) {
+        if !self.config.is_enabled() {
+            return;
+        }
+        let now = Instant::now();
+        let mut staff = self
+            .staff
+            .lock()
+            .expect("Refresh registry lock is poisoned");
+        staff
+            .entry(staff_id)
+            .or_insert_with(|| RefreshEntry {
+                last_requested_at: now,
+                next_refresh_at: now + jittered(self.config.interval),
+                page_count: 1,
+            })
+            .last_requested_at = now;
+    }
+
+    /// Forgets idle staff and returns the ids and page counts of those due for
+    /// a refresh, most overdue first.
+    fn due(&self, now: Instant) -> Vec<(i64, u32)> {
+        let mut staff = self
+            .staff
+            .lock()
+            .expect("Refresh registry lock is poisoned");
+        staff.retain(|_, e| now.duration_since(e.last_requested_at) < self.config.idle_after);
+        let mut due: Vec<(Instant, i64, u32)> = staff
+            .iter()
+            .filter(|(_, e)| e.next_refresh_at <= now)
+            .map(|(id, e)| (e.next_refresh_at, *id, e.page_count))
+            .collect();
+        due.sort_unstable();
+        due.into_iter()
+            .map(|(_, id, page_count)| (id, page_count))
+            .collect()
+    }
+
+    fn reschedule(&self, staff_id: i64, page_count: Option<u32>) {
+        let mut staff = self
+            .staff
+            .lock()
+            .expect("Refresh registry lock is poisoned");
+        if let Some(entry) = staff.get_mut(&staff_id) {
+            entry.next_refresh_at = Instant::now() + jittered(self.config.interval);
+            if let Some(page_count) = page_count {
+                entry.page_count = page_count;
+            }
+        }
+    }
+}
+
+/// Background task that re-fetches the production credits of recently
+/// requested staff before their stored copy goes stale, so plain staff feeds
+/// rarely wait on Anilist. Character roles, episodes, studios and characters
+/// only live in the response cache and aren't refreshed, so feeds that use them
+/// still wait on Anilist once a cached response expires.
+pub struct Refresher {
+    repository: AnilistRepository,
+    config: RefreshConfig,
+}
+
+impl Refresher {
+    pub fn new(repository: AnilistRepository, config: RefreshConfig) -> Self {
+        Refresher { repository, config }
+    }
+
+    /// Runs forever. Due staff are refreshed one at a time, most overdue
+    /// first, for as long as the request budget lasts; the rest wait for a
+    /// later tick.
+    pub async fn run(self) {
+        let capacity = self.config.requests_per_minute as f64;
+        let mut budget = capacity;
+        let mut last_refill = Instant::now();
+        let mut ticker = time::interval(REFRESH_TICK);
+        loop {
+            ticker.tick().await;
+            let now = Instant::now();
+            budget = (budget + now.duration_since(last_refill).as_secs_f64() * capacity / 60.0)
+                .min(capacity);
+            last_refill = now;
+
+            let registry = &self.repository.refresh_registry;
+            for (staff_id, page_count) in registry.due(now) {
+                let cost = (page_count as f64).min(capacity);
+                if budget < cost {
+                    break;
+                }
+                budget -= cost;
+                match self.repository.refresh_staff_filmography(staff_id).await {
+                    Ok(filmography) => {
+                        let page_count =
+                            anilist_utils::staff_media_page_count(filmography.credits.len());
+                        budget += cost - page_count as f64;
+                        registry.reschedule(staff_id, Some(page_count));
+                    }
+                    Err(e) => {
+                        warn!("Background refresh of staff {} failed: {}", staff_id, e);
+                        registry.reschedule(staff_id, None);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn jittered(interval: Duration) -> Duration {
+    interval.mul_f64(1.0 + REFRESH_JITTER * (fastrand::f64() * 2.0 - 1.0))
+}
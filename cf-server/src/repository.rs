@@ -0,0 +1,191 @@
+use crate::{
+    anilist_utils,
+    clients::{
+        search_query, staff_by_ids_query::StaffByIdsQueryStaffResults, AnilistClient, CacheMode,
+    },
+    errors,
+    errors::ServiceError,
+    models::{
+        AiredEpisode, CharacterAppearances, CharacterCredit, MediaCredit, StaffFilmography,
+        StudioFilmography,
+    },
+    refresher::RefreshRegistry,
+    storage::Storage,
+};
+use actix_web::http::StatusCode;
+use chrono::Utc;
+use std::collections::HashMap;
+
+/// Anilist's page size limit.
+const STAFF_BY_IDS_PER_PAGE: usize = 50;
+
+/// Entry point for route handlers to Anilist data. Stored copies younger than
+/// `stored_fresh_for` are served as is, which is the usual case while the
+/// background refresher keeps requested staff up to date. Otherwise fresh data
+/// is asked for and saved to `storage`, which then answers while Anilist is down.
+/// Only staff and their production credits are stored; everything else comes
+/// from Anilist or its response cache.
+#[derive(Clone)]
+pub struct AnilistRepository {
+    pub client: AnilistClient,
+    pub storage: Storage,
+    pub refresh_registry: RefreshRegistry,
+    pub stored_fresh_for: chrono::Duration,
+}
+
+impl AnilistRepository {
+    pub async fn staff_filmography(&self, id: i64) -> Result<StaffFilmography, ServiceError> {
+        self.refresh_registry.record_request(id);
+        let mut stored = self.storage.load_staff_filmography(id).await?;
+        let now = Utc::now();
+        if let Some(filmography) = stored.take_if(|f| {
+            f.fetched_at
+                .is_some_and(|fetched_at| now - fetched_at < self.stored_fresh_for)
+        }) {
+            return Ok(filmography);
+        }
+
+        match self.fetch_and_save(id, CacheMode::Cached).await {
+            Ok(filmography) => Ok(filmography),
+            Err(e) if is_anilist_unavailable(&e) => match stored {
+                Some(filmography) => {
+                    warn!("Serving staff {} from storage, Anilist failed: {}", id, e);
+                    Ok(filmography)
+                }
+                None => Err(e),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Fetches the staff from Anilist, skipping the response cache, and saves
+    /// the result.
+    pub async fn refresh_staff_filmography(
+        &self,
+        id: i64,
+    ) -> Result<StaffFilmography, ServiceError> {
+        self.fetch_and_save(id, CacheMode::Refresh).await
+    }
+
+    pub async fn staff_character_credits(
+        &self,
+        id: i64,
+    ) -> Result<Vec<CharacterCredit>, ServiceError> {
+        let mut credits =
+            anilist_utils::fetch_staff_character_credits(&self.client, id, CacheMode::Cached)
+                .await?;
+        self.date_undated_media(&mut credits).await?;
+        Ok(credits)
+    }
+
+    pub async fn aired_episodes(
+        &self,
+        media_ids: &[i64],
+    ) -> Result<Vec<AiredEpisode>, ServiceError> {
+        anilist_utils::fetch_aired_episodes(&self.client, media_ids, CacheMode::Cached).await
+    }
+
+    pub async fn character_appearances(
+        &self,
+        id: i64,
+    ) -> Result<CharacterAppearances, ServiceError> {
+        let mut appearances =
+            anilist_utils::fetch_character_appearances(&self.client, id, CacheMode::Cached).await?;
+        self.date_undated_media(&mut appearances.appearances)
+            .await?;
+        Ok(appearances)
+    }
+
+    pub async fn studio_filmography(&self, id: i64) -> Result<StudioFilmography, ServiceError> {
+        let mut filmography =
+            anilist_utils::fetch_studio_filmography(&self.client, id, CacheMode::Cached).await?;
+        self.date_undated_media(&mut filmography.credits).await?;
+        Ok(filmography)
+    }
+
+    pub async fn search(
+        &self,
+        query: &str,
+        per_page: i64,
+    ) -> Result<search_query::ResponseData, ServiceError> {
+        self.client.search(query, per_page).await
+    }
+
+    /// Looks up staff by id, in the order given. Ids Anilist doesn't know are
+    /// left out.
+    pub async fn staff_by_ids(
+        &self,
+        ids: &[i64],
+    ) -> Result<Vec<StaffByIdsQueryStaffResults>, ServiceError> {
+        let mut staff_by_id: HashMap<i64, StaffByIdsQueryStaffResults> = HashMap::new();
+        for chunk in ids.chunks(STAFF_BY_IDS_PER_PAGE) {
+            let results = self
+                .client
+                .get_staff_by_ids(chunk)
+                .await?
+                .staff
+                .and_then(|page| page.results)
+                .unwrap_or_default();
+            staff_by_id.extend(results.into_iter().flatten().map(|s| (s.id, s)));
+        }
+        Ok(ids.iter().filter_map(|id| staff_by_id.remove(id)).collect())
+    }
+
+    /// Sets when the media without a start year were first seen, which is what
+    /// feeds date them by.
+    async fn date_undated_media<C: MediaCredit>(
+        &self,
+        credits: &mut [C],
+    ) -> Result<(), ServiceError> {
+        let mut media_ids: Vec<i64> = credits
+            .iter()
+            .map(|c| c.media())
+            .filter(|m| m.start_date.year.is_none())
+            .map(|m| m.id)
+            .collect();
+        media_ids.sort_unstable();
+        media_ids.dedup();
+        let first_seen = self
+            .storage
+            .undated_media_first_seen(&media_ids, Utc::now())
+            .await?;
+        for credit in credits {
+            credit.set_first_seen_at(first_seen.get(&credit.media().id).copied());
+        }
+        Ok(())
+    }
+
+    async fn fetch_and_save(
+        &self,
+        id: i64,
+        cache_mode: CacheMode,
+    ) -> Result<StaffFilmography, ServiceError> {
+        let filmography =
+            anilist_utils::fetch_staff_filmography(&self.client, id, cache_mode).await?;
+        let fetched_at = filmography.fetched_at.unwrap_or_else(Utc::now);
+        self.storage
+            .save_staff_filmography(&filmography, fetched_at)
+            .await?;
+        self.storage
+            .load_staff_filmography(id)
+            .await?
+            .ok_or(errors::internal_logic_error(
+                "Staff is not stored right after saving it",
+            ))
+    }
+}
+
+/// Errors that say nothing about the requested data, only that Anilist can't be
+/// reached or is overloaded right now.
+fn is_anilist_unavailable(e: &ServiceError) -> bool {
+    match e {
+        ServiceError::InternalError => true,
+        ServiceError::AnilistTimeout => true,
+        ServiceError::AnilistRateLimited(_e) => true,
+        ServiceError::AnilistError(anilist_error) => {
+            anilist_error.status_code.is_server_error()
+                || anilist_error.status_code == StatusCode::TOO_MANY_REQUESTS
+        }
+        _ => false,
+    }
+}
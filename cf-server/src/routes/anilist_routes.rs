@@ -1,207 +1,238 @@
-use super::super::{errors, errors::ServiceError};
-use crate::clients::staff_media_query::StaffMediaQueryStaffStaffMediaNodes;
+use super::super::errors::ServiceError;
+use crate::anilist_utils::{self, CharacterFeedOptions, StaffFeedOptions, StudioFeedOptions};
+use crate::feeds::{Feed, FeedFormat};
 use crate::AppData;
-use actix_web::{get, http::header::ContentType, web, HttpResponse, Responder};
-use chrono::naive::NaiveDate;
-use rss::{Channel, ChannelBuilder, Image, ImageBuilder, Item, ItemBuilder};
-
-const RSS_2_SPECIFICATION_URL: &str = "https://validator.w3.org/feed/docs/rss2.html";
-const NO_STAFF_DESCRIPTION: &str = "No description provided by Anilist for this staff.";
-const STAFF_NONE: &str = "Staff is None";
-const STAFF_MEDIA_BATCH_SIZE: i64 = 25;
-const ANILIST_TTL: &str = "360";
+use actix_web::{
+    get,
+    http::header::{self, HeaderValue},
+    web, HttpRequest, HttpResponse, Responder,
+};
+use serde::Deserialize;
+use std::future::Future;
+
+/// Feed format named by the first segment of a feed URL, e.g. `/atom/...`.
+/// Feed routes only match these segments, so they don't shadow routes like
+/// `/opml/anilist/staff`.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum FeedPath {
+    Rss,
+    Atom,
+    Json,
+}
 
-struct AnilistMedia {
-    role: String,
-    media: StaffMediaQueryStaffStaffMediaNodes,
+#[derive(Debug, Deserialize)]
+pub(super) struct StaffIdsQuery {
+    /// Comma separated Anilist staff ids.
+    pub(super) ids: String,
 }
 
-#[get("/rss/anilist/staff/{anilist_id}")]
-async fn get_anilist_staff_rss_feed(
-    path: web::Path<i64>,
+#[get("/{format:rss|atom|json}/anilist/staff/{anilist_id}")]
+async fn get_anilist_staff_feed(
+    req: HttpRequest,
+    path: web::Path<(FeedPath, i64)>,
+    query_params: web::Query<StaffFeedOptions>,
     data: AppData,
 ) -> Result<impl Responder, ServiceError> {
-    let mut current_page: i64 = 1;
-
-    let id: i64 = path.into_inner();
-    let client = &data.anilist_client;
-    let staff = client
-        .get_staff_media(id, STAFF_MEDIA_BATCH_SIZE, current_page)
-        .await?
-        .staff
-        .ok_or(errors::anilist_data_format(STAFF_NONE))?;
-    let staff_media = staff
-        .staff_media
-        .ok_or(errors::anilist_data_format("Staff.staffMedia is None"))?;
-
-    let mut roles = staff_media.edges.ok_or(errors::anilist_data_format(
-        "Staff.staffMedia.edges is None",
-    ))?;
-    let mut media = staff_media.nodes.ok_or(errors::anilist_data_format(
-        "Staff.staffMedia.nodes is None",
-    ))?;
-
-    let anilist_staff_name = staff
-        .name
-        .ok_or(errors::anilist_data_format("Staff.name is None"))?;
-    let staff_name_collection: Vec<Option<String>> =
-        vec![anilist_staff_name.full, anilist_staff_name.native];
-    let staff_name: String = staff_name_collection
-        .into_iter()
-        .filter_map(|name| name)
-        .collect::<Vec<String>>()
-        .join(", ");
-
-    let mut media_in_page = media.len();
-    let mut zipped_role_media = vec![roles.into_iter().zip(media.into_iter())];
-
-    current_page += 1;
-    while media_in_page == (STAFF_MEDIA_BATCH_SIZE as usize) {
-        let staff_media_page = client
-            .get_staff_media(id, STAFF_MEDIA_BATCH_SIZE, current_page)
-            .await?
-            .staff
-            .ok_or(errors::anilist_data_format(STAFF_NONE))?
-            .staff_media
-            .ok_or(errors::anilist_data_format("Staff.staffMedia is None"))?;
-        roles = staff_media_page.edges.ok_or(errors::anilist_data_format(
-            "Staff.staffMedia.edges is None",
-        ))?;
-        media = staff_media_page.nodes.ok_or(errors::anilist_data_format(
-            "Staff.staffMedia.nodes is None",
-        ))?;
-        media_in_page = media.len();
-        current_page += 1;
+    let (feed_path, id) = path.into_inner();
+    let query_params = query_params.into_inner();
+    let filters = query_params.filters()?;
+    let builder_data = data.clone();
+    feed_path_response(
+        &req,
+        &data,
+        feed_path,
+        &format!("anilist:staff:{}", id),
+        async move {
+            anilist_utils::load_staff_feed(
+                &builder_data.anilist_repository,
+                id,
+                &query_params,
+                &filters,
+            )
+            .await
+        },
+    )
+    .await
+}
 
-        zipped_role_media.push(roles.into_iter().zip(media.into_iter()));
-    }
+#[get("/{format:rss|atom|json}/anilist/staff")]
+async fn get_anilist_combined_feed(
+    req: HttpRequest,
+    path: web::Path<FeedPath>,
+    ids: web::Query<StaffIdsQuery>,
+    query_params: web::Query<StaffFeedOptions>,
+    data: AppData,
+) -> Result<impl Responder, ServiceError> {
+    let staff_ids = anilist_utils::parse_staff_ids(&ids.ids)?;
+    let link = format!(
+        "{}/rss/anilist/staff?ids={}",
+        site_url(&req, &data),
+        staff_ids
+            .iter()
+            .map(i64::to_string)
+            .collect::<Vec<String>>()
+            .join(",")
+    );
+    let query_params = query_params.into_inner();
+    let filters = query_params.filters()?;
+    let builder_data = data.clone();
+    feed_path_response(
+        &req,
+        &data,
+        path.into_inner(),
+        &format!("anilist:staff:combined:{}", link),
+        async move {
+            anilist_utils::build_combined_staff_feed(
+                &builder_data.anilist_repository,
+                &staff_ids,
+                &query_params,
+                &filters,
+                link,
+            )
+            .await
+        },
+    )
+    .await
+}
 
-    let mut staff_channel_items: Vec<Item> = zipped_role_media
-        .into_iter()
-        .flatten()
-        .map(|(r, m)| {
-            let has_role = r.is_some();
-            let has_media = m.is_some();
-            if !has_media || !has_role {
-                return Ok(None);
-            }
-            let start_date = m
-                .as_ref()
-                .ok_or(errors::internal_logic_error("Staff.staffMedia[] is None"))?
-                .start_date
-                .as_ref()
-                .ok_or(errors::anilist_data_format("Staff.staffMedia.startDate"))?;
-            let has_start_date =
-                start_date.year.is_some() && start_date.month.is_some() && start_date.day.is_some();
-            let string_role = r
-                .ok_or(errors::internal_logic_error(
-                    "None role after is_some check",
-                ))?
-                .staff_role
-                .ok_or(errors::anilist_data_format("Staff.staffMedia.edges"))?;
-            if has_start_date {
-                Ok(Some(AnilistMedia {
-                    role: string_role,
-                    media: m.ok_or(errors::internal_logic_error(
-                        "Staff.staffMedia.nodes[] is None after is_some check",
-                    ))?,
-                }))
-            } else {
-                Ok(None)
-            }
-        })
-        .collect::<Result<Vec<Option<AnilistMedia>>, ServiceError>>()?
-        .into_iter()
-        .filter(|o_m| o_m.is_some())
-        .map(|o| {
-            let anilist_media =
-                o.ok_or(errors::internal_logic_error("Logically cannot be None"))?;
-            let m = anilist_media.media;
-            let mut title: String = "Anilist has no title".to_string();
-            if let Some(t) = m.title {
-                let mut english = t.romaji;
-                if t.english.is_some() {
-                    english = t.english;
-                }
+#[get("/{format:rss|atom|json}/anilist/studio/{anilist_id}")]
+async fn get_anilist_studio_feed(
+    req: HttpRequest,
+    path: web::Path<(FeedPath, i64)>,
+    query_params: web::Query<StudioFeedOptions>,
+    data: AppData,
+) -> Result<impl Responder, ServiceError> {
+    let (feed_path, id) = path.into_inner();
+    let query_params = query_params.into_inner();
+    let media_filter = query_params.media_filter()?;
+    let builder_data = data.clone();
+    feed_path_response(
+        &req,
+        &data,
+        feed_path,
+        &format!("anilist:studio:{}", id),
+        async move {
+            anilist_utils::load_studio_feed(
+                &builder_data.anilist_repository,
+                id,
+                &query_params,
+                &media_filter,
+            )
+            .await
+        },
+    )
+    .await
+}
 
-                let anime_name_collection: Vec<Option<String>> = vec![english, t.native];
-                title = anime_name_collection
-                    .into_iter()
-                    .filter(|name| name.is_some())
-                    .map(|name| {
-                        name.ok_or(errors::internal_logic_error(
-                            "name cannot be None after check",
-                        ))
-                    })
-                    .collect::<Result<Vec<String>, ServiceError>>()?
-                    .join(", ");
-                title = staff_name.clone() + " as " + &anilist_media.role + " on " + &title;
-            }
+#[get("/{format:rss|atom|json}/anilist/character/{anilist_id}")]
+async fn get_anilist_character_feed(
+    req: HttpRequest,
+    path: web::Path<(FeedPath, i64)>,
+    query_params: web::Query<CharacterFeedOptions>,
+    data: AppData,
+) -> Result<impl Responder, ServiceError> {
+    let (feed_path, id) = path.into_inner();
+    let query_params = query_params.into_inner();
+    let media_filter = query_params.media_filter()?;
+    let builder_data = data.clone();
+    feed_path_response(
+        &req,
+        &data,
+        feed_path,
+        &format!("anilist:character:{}", id),
+        async move {
+            anilist_utils::load_character_feed(
+                &builder_data.anilist_repository,
+                id,
+                &query_params,
+                &media_filter,
+            )
+            .await
+        },
+    )
+    .await
+}
 
-            let start_date = m.start_date.as_ref().ok_or(errors::internal_logic_error(
-                "Staff.staffMedia[].startDate is None after check",
-            ))?;
-            let year = start_date.year.ok_or(errors::internal_logic_error(
-                "Staff.staffMedia[].startDate.year is None after check",
-            ))?;
-            let month = start_date.month.ok_or(errors::internal_logic_error(
-                "Staff.staffMedia[].startDate.month is None after check",
-            ))?;
-            let day = start_date.day.ok_or(errors::internal_logic_error(
-                "Staff.staffMedia[].startDate.day is None after check",
-            ))?;
-            let naive_date = NaiveDate::from_ymd(year as i32, month as u32, day as u32);
+/// Responds with the feed `build` makes, in the format the path names.
+/// `/rss/` URLs serve whichever format the reader's Accept asks for, falling
+/// back to RSS.
+pub(super) async fn feed_path_response(
+    req: &HttpRequest,
+    data: &AppData,
+    feed_path: FeedPath,
+    feed: &str,
+    build: impl Future<Output = Result<Feed, ServiceError>> + Send + 'static,
+) -> Result<HttpResponse, ServiceError> {
+    let format = match feed_path {
+        FeedPath::Rss => FeedFormat::negotiate(req),
+        FeedPath::Atom => FeedFormat::Atom,
+        FeedPath::Json => FeedFormat::Json,
+    };
+    let key = feed_build_key(feed, format, req);
+    let response = shared_feed_response(req, data, key, format, build).await?;
+    Ok(match feed_path {
+        FeedPath::Rss => vary_on_accept(response),
+        _ => response,
+    })
+}
 
-            Ok(ItemBuilder::default()
-                .title(Some(title))
-                .link(m.site_url)
-                .description(m.description)
-                .pub_date(Some(naive_date.to_string()))
-                .build())
-        })
-        .collect::<Result<Vec<Item>, ServiceError>>()?;
+/// Builds and renders the feed, sharing one build between every concurrent
+/// request with the same `key`.
+async fn shared_feed_response(
+    req: &HttpRequest,
+    data: &AppData,
+    key: String,
+    format: FeedFormat,
+    build: impl Future<Output = Result<Feed, ServiceError>> + Send + 'static,
+) -> Result<HttpResponse, ServiceError> {
+    let rendered = data
+        .feed_builds
+        .run(
+            key,
+            move || async move { Ok(build.await?.rendered(format)) },
+        )
+        .await?;
+
+    Ok(rendered.to_response(req))
+}
 
-    staff_channel_items.sort_by(|a, b| {
-        let b_date = b.pub_date().expect("Logically must have pub_date");
-        let a_date = a.pub_date().expect("Logically must have pub_date");
-        b_date.cmp(a_date)
-    });
+/// Identifies a feed build by what it's for, its format and its query string.
+/// Parameters are sorted so the same options given in a different order share
+/// a build.
+fn feed_build_key(feed: &str, format: FeedFormat, req: &HttpRequest) -> String {
+    let mut params: Vec<&str> = req
+        .query_string()
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .collect();
+    params.sort_unstable();
+    format!("{}:{:?}:{}", feed, format, params.join("&"))
+}
 
-    let site_url = staff
-        .site_url
-        .ok_or(errors::anilist_data_format("Staff.siteUrl is None"))?;
-    let image_url = staff
-        .image
-        .ok_or(errors::anilist_data_format("Staff.image is None"))?
-        .large
-        .ok_or(errors::anilist_data_format("Staff.image.large is None"))?;
-    let rss_image: Image = ImageBuilder::default()
-        .title(&staff_name)
-        .link(&site_url)
-        .url(&image_url)
-        .build();
-    let mut staff_description = NO_STAFF_DESCRIPTION.to_string();
-    if staff.description.is_some() {
-        staff_description = staff.description.ok_or(errors::internal_logic_error(
-            "Staff.description cannot be None",
-        ))?;
+/// Scheme and host for absolute links back to this server. Without a
+/// configured public URL they come from the request, which behind a proxy is
+/// only right when it forwards the scheme and host.
+pub(super) fn site_url(req: &HttpRequest, data: &AppData) -> String {
+    if let Some(public_url) = &data.public_url {
+        return public_url.clone();
     }
+    let connection_info = req.connection_info();
+    format!("{}://{}", connection_info.scheme(), connection_info.host())
+}
 
-    let staff_channel: Channel = ChannelBuilder::default()
-        .title(&staff_name)
-        .link(&site_url)
-        .description(&staff_description)
-        .image(rss_image)
-        .docs(RSS_2_SPECIFICATION_URL.to_string())
-        .items(staff_channel_items)
-        .ttl(Some(ANILIST_TTL.to_string()))
-        .build();
-
-    Ok(HttpResponse::Ok()
-        .content_type(ContentType::xml())
-        .body(staff_channel.to_string()))
+/// The format of negotiated feeds depends on Accept, so shared caches must keep
+/// one copy per value.
+fn vary_on_accept(mut response: HttpResponse) -> HttpResponse {
+    response
+        .headers_mut()
+        .insert(header::VARY, HeaderValue::from_static("accept"));
+    response
 }
 
 pub fn init(cfg: &mut web::ServiceConfig) {
-    cfg.service(get_anilist_staff_rss_feed);
+    cfg.service(get_anilist_staff_feed);
+    cfg.service(get_anilist_combined_feed);
+    cfg.service(get_anilist_studio_feed);
+    cfg.service(get_anilist_character_feed);
 }
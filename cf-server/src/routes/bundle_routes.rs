@@ -0,0 +1,275 @@
+use super::super::{errors, errors::ServiceError};
+use super::anilist_routes::{feed_path_response, site_url, FeedPath};
+use super::page_routes::TemplateToResponse;
+use crate::anilist_utils;
+use crate::bundles::{self, Bundle, BundleInput};
+use crate::AppData;
+use actix_web::{body::BoxBody, get, post, put, web, HttpRequest, HttpResponse, Responder};
+use askama::Template;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+const EDIT_TOKEN_HEADER: &str = "X-Edit-Token";
+const NO_BUNDLE: &str = "No bundle with this slug";
+
+/// The bundle's staff as one combined feed, titled with the bundle's name.
+#[get("/{format:rss|atom|json}/bundle/{slug}")]
+async fn get_bundle_feed(
+    req: HttpRequest,
+    path: web::Path<(FeedPath, String)>,
+    data: AppData,
+) -> Result<impl Responder, ServiceError> {
+    let (feed_path, slug) = path.into_inner();
+    let link = format!("{}/rss/bundle/{}", site_url(&req, &data), slug);
+    let builder_data = data.clone();
+    feed_path_response(
+        &req,
+        &data,
+        feed_path,
+        &format!("bundle:{}", link),
+        async move {
+            let bundle = builder_data
+                .storage
+                .load_bundle(&slug)
+                .await?
+                .ok_or(errors::not_found(NO_BUNDLE))?;
+            let filters = bundle.options.filters()?;
+            let mut feed = anilist_utils::build_combined_staff_feed(
+                &builder_data.anilist_repository,
+                &bundle.staff_ids,
+                &bundle.options,
+                &filters,
+                link,
+            )
+            .await?;
+            feed.title = bundle.name;
+            Ok(feed)
+        },
+    )
+    .await
+}
+
+#[derive(Debug, Deserialize)]
+struct NewBundleRequest {
+    slug: String,
+    #[serde(flatten)]
+    input: BundleInput,
+}
+
+#[derive(Serialize)]
+struct NewBundleResponse {
+    /// Only ever shown here, it's needed for every later edit.
+    edit_token: String,
+    bundle: Bundle,
+}
+
+#[post("/api/bundles")]
+async fn create_bundle(
+    body: web::Json<NewBundleRequest>,
+    data: AppData,
+) -> Result<impl Responder, ServiceError> {
+    let request = body.into_inner();
+    let edit_token = save_new_bundle(&data, &request.slug, request.input).await?;
+    let bundle = load_bundle(&data, &request.slug).await?;
+
+    Ok(HttpResponse::Created().json(NewBundleResponse { edit_token, bundle }))
+}
+
+#[get("/api/bundles/{slug}")]
+async fn get_bundle(
+    path: web::Path<String>,
+    data: AppData,
+) -> Result<impl Responder, ServiceError> {
+    Ok(HttpResponse::Ok().json(load_bundle(&data, &path).await?))
+}
+
+/// Replaces the bundle, given its edit token in the `X-Edit-Token` header.
+#[put("/api/bundles/{slug}")]
+async fn update_bundle(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<BundleInput>,
+    data: AppData,
+) -> Result<impl Responder, ServiceError> {
+    let edit_token = req
+        .headers()
+        .get(EDIT_TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .ok_or(errors::forbidden("X-Edit-Token header is missing"))?;
+    save_bundle_edit(&data, &path, edit_token, body.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(load_bundle(&data, &path).await?))
+}
+
+#[derive(Template)]
+#[template(path = "bundle_form.html")]
+struct BundleFormTemplate {
+    action: String,
+    editing: bool,
+    slug: String,
+    name: String,
+    staff_ids: String,
+    options: String,
+}
+
+#[derive(Template)]
+#[template(path = "bundle_saved.html")]
+struct BundleSavedTemplate {
+    name: String,
+    staff_count: usize,
+    rss_link: String,
+    atom_link: String,
+    json_link: String,
+    opml_link: String,
+    edit_link: String,
+    edit_token: Option<String>,
+}
+
+/// Bundle form fields. Staff ids are comma separated and options are a feed
+/// query string, e.g. "include_role=Director&media_type=ANIME".
+#[derive(Debug, Deserialize)]
+struct BundleForm {
+    slug: Option<String>,
+    name: String,
+    staff_ids: String,
+    options: String,
+    edit_token: Option<String>,
+}
+
+impl BundleForm {
+    fn to_input(&self) -> Result<BundleInput, ServiceError> {
+        Ok(BundleInput {
+            name: self.name.clone(),
+            staff_ids: anilist_utils::parse_staff_ids(&self.staff_ids)?,
+            options: bundles::options_from_query(&self.options)?,
+        })
+    }
+}
+
+#[get("/bundles/new")]
+async fn new_bundle_form() -> Result<HttpResponse<BoxBody>, ServiceError> {
+    BundleFormTemplate {
+        action: "/bundles".to_string(),
+        editing: false,
+        slug: String::new(),
+        name: String::new(),
+        staff_ids: String::new(),
+        options: String::new(),
+    }
+    .to_response()
+}
+
+#[post("/bundles")]
+async fn create_bundle_from_form(
+    form: web::Form<BundleForm>,
+    data: AppData,
+) -> Result<HttpResponse<BoxBody>, ServiceError> {
+    let slug = form.slug.clone().unwrap_or_default();
+    let edit_token = save_new_bundle(&data, &slug, form.to_input()?).await?;
+    let bundle = load_bundle(&data, &slug).await?;
+
+    bundle_saved_template(bundle, Some(edit_token)).to_response()
+}
+
+#[get("/bundles/{slug}/edit")]
+async fn edit_bundle_form(
+    path: web::Path<String>,
+    data: AppData,
+) -> Result<HttpResponse<BoxBody>, ServiceError> {
+    let bundle = load_bundle(&data, &path).await?;
+    BundleFormTemplate {
+        action: format!("/bundles/{}", bundle.slug),
+        editing: true,
+        staff_ids: bundle
+            .staff_ids
+            .iter()
+            .map(i64::to_string)
+            .collect::<Vec<String>>()
+            .join(","),
+        options: bundles::options_to_query(&bundle.options)?,
+        slug: bundle.slug,
+        name: bundle.name,
+    }
+    .to_response()
+}
+
+#[post("/bundles/{slug}")]
+async fn update_bundle_from_form(
+    path: web::Path<String>,
+    form: web::Form<BundleForm>,
+    data: AppData,
+) -> Result<HttpResponse<BoxBody>, ServiceError> {
+    let edit_token = form.edit_token.clone().unwrap_or_default();
+    save_bundle_edit(&data, &path, &edit_token, form.to_input()?).await?;
+    let bundle = load_bundle(&data, &path).await?;
+
+    bundle_saved_template(bundle, None).to_response()
+}
+
+/// Validates and saves a new bundle, returning its freshly made edit token.
+async fn save_new_bundle(
+    data: &AppData,
+    slug: &str,
+    input: BundleInput,
+) -> Result<String, ServiceError> {
+    bundles::validate_slug(slug)?;
+    let input = input.validated()?;
+    let edit_token = bundles::new_edit_token()?;
+    data.storage
+        .create_bundle(
+            slug,
+            &input,
+            &bundles::edit_token_hash(&edit_token),
+            Utc::now(),
+        )
+        .await?;
+    Ok(edit_token)
+}
+
+async fn save_bundle_edit(
+    data: &AppData,
+    slug: &str,
+    edit_token: &str,
+    input: BundleInput,
+) -> Result<(), ServiceError> {
+    let input = input.validated()?;
+    data.storage
+        .update_bundle(
+            slug,
+            &input,
+            &bundles::edit_token_hash(edit_token),
+            Utc::now(),
+        )
+        .await
+}
+
+async fn load_bundle(data: &AppData, slug: &str) -> Result<Bundle, ServiceError> {
+    data.storage
+        .load_bundle(slug)
+        .await?
+        .ok_or(errors::not_found(NO_BUNDLE))
+}
+
+fn bundle_saved_template(bundle: Bundle, edit_token: Option<String>) -> BundleSavedTemplate {
+    BundleSavedTemplate {
+        name: bundle.name,
+        staff_count: bundle.staff_ids.len(),
+        rss_link: format!("/rss/bundle/{}", bundle.slug),
+        atom_link: format!("/atom/bundle/{}", bundle.slug),
+        json_link: format!("/json/bundle/{}", bundle.slug),
+        opml_link: format!("/opml/bundle/{}", bundle.slug),
+        edit_link: format!("/bundles/{}/edit", bundle.slug),
+        edit_token,
+    }
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_bundle_feed);
+    cfg.service(create_bundle);
+    cfg.service(get_bundle);
+    cfg.service(update_bundle);
+    cfg.service(new_bundle_form);
+    cfg.service(create_bundle_from_form);
+    cfg.service(edit_bundle_form);
+    cfg.service(update_bundle_from_form);
+}
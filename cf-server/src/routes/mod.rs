@@ -1,7 +1,11 @@
 mod anilist_routes;
+mod bundle_routes;
 mod health_routes;
+mod opml_routes;
 mod page_routes;
 
 pub use anilist_routes::init as init_anilist_routes;
+pub use bundle_routes::init as init_bundle_routes;
 pub use health_routes::init as init_health_routes;
+pub use opml_routes::init as init_opml_routes;
 pub use page_routes::init as init_page_routes;
@@ -0,0 +1,147 @@
+use super::super::{errors, errors::ServiceError};
+use super::anilist_routes::{site_url, StaffIdsQuery};
+use super::page_routes::{
+    anilist_rss_link, anilist_staff_link, search_result, CompletedSearchInfo,
+    SearchResultsTemplate, TemplateToResponse,
+};
+use crate::anilist_utils;
+use crate::opml::{self, OpmlOutline};
+use crate::AppData;
+use actix_multipart::form::{bytes::Bytes, MultipartForm};
+use actix_web::{body::BoxBody, get, post, web, HttpRequest, HttpResponse, Responder};
+use askama::Template;
+use std::collections::HashMap;
+
+const OPML_CONTENT_TYPE: &str = "text/x-opml; charset=utf-8";
+const OPML_CONTENT_DISPOSITION: &str = "attachment; filename=\"creator-follower.opml\"";
+const STAFF_OPML_TITLE: &str = "Creator Follower";
+/// Most staff one import looks up, each 50 of which cost an Anilist request.
+const MAX_IMPORTED_STAFF: usize = 200;
+
+#[get("/opml/anilist/staff")]
+async fn get_staff_opml(
+    req: HttpRequest,
+    ids: web::Query<StaffIdsQuery>,
+    data: AppData,
+) -> Result<impl Responder, ServiceError> {
+    let staff_ids = anilist_utils::parse_staff_ids(&ids.ids)?;
+    opml_response(&req, &data, STAFF_OPML_TITLE, &staff_ids).await
+}
+
+#[get("/opml/bundle/{slug}")]
+async fn get_bundle_opml(
+    req: HttpRequest,
+    path: web::Path<String>,
+    data: AppData,
+) -> Result<impl Responder, ServiceError> {
+    let bundle = data
+        .storage
+        .load_bundle(&path)
+        .await?
+        .ok_or(errors::not_found("No bundle with this slug"))?;
+    opml_response(&req, &data, &bundle.name, &bundle.staff_ids).await
+}
+
+/// One outline per staff feed, titled with the staff's name on Anilist.
+async fn opml_response(
+    req: &HttpRequest,
+    data: &AppData,
+    title: &str,
+    staff_ids: &[i64],
+) -> Result<HttpResponse, ServiceError> {
+    let mut names: HashMap<i64, String> = data
+        .anilist_repository
+        .staff_by_ids(staff_ids)
+        .await?
+        .into_iter()
+        .filter_map(|s| {
+            let name = s.name?;
+            let names: Vec<String> = [name.full, name.native].into_iter().flatten().collect();
+            Some((s.id, names.join(", ")))
+        })
+        .collect();
+    let site_url = site_url(req, data);
+    let outlines: Vec<OpmlOutline> = staff_ids
+        .iter()
+        .map(|id| OpmlOutline {
+            title: names
+                .remove(id)
+                .unwrap_or_else(|| format!("Anilist staff {}", id)),
+            xml_url: site_url.clone() + &anilist_rss_link(*id),
+            html_url: anilist_staff_link(*id),
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok()
+        .content_type(OPML_CONTENT_TYPE)
+        .insert_header(("Content-Disposition", OPML_CONTENT_DISPOSITION))
+        .body(opml::to_opml(title, &outlines)?))
+}
+
+#[derive(Template)]
+#[template(path = "opml_import.html")]
+struct OpmlImportTemplate {}
+
+#[derive(MultipartForm)]
+struct OpmlUpload {
+    #[multipart(limit = "1MB")]
+    opml: Bytes,
+}
+
+#[get("/opml/import")]
+async fn opml_import_form() -> Result<HttpResponse<BoxBody>, ServiceError> {
+    OpmlImportTemplate {}.to_response()
+}
+
+/// Lists the creators whose feeds an uploaded OPML file subscribes to, on the
+/// search results page.
+#[post("/opml/import")]
+async fn import_opml(
+    MultipartForm(upload): MultipartForm<OpmlUpload>,
+    data: AppData,
+) -> Result<HttpResponse<BoxBody>, ServiceError> {
+    let opml = std::str::from_utf8(&upload.opml.data)
+        .map_err(|_| errors::invalid_parameter("opml", "is not UTF-8".to_string()))?;
+    let staff_ids = opml::staff_ids_from_opml(opml)?;
+    if staff_ids.len() > MAX_IMPORTED_STAFF {
+        return Err(errors::invalid_parameter(
+            "opml",
+            format!("follows more than {} staff feeds", MAX_IMPORTED_STAFF),
+        ));
+    }
+    let search_results = data
+        .anilist_repository
+        .staff_by_ids(&staff_ids)
+        .await?
+        .into_iter()
+        .map(|s| {
+            let names = match s.name {
+                Some(name) => vec![name.full, name.native],
+                None => Vec::new(),
+            };
+            search_result(
+                s.id,
+                s.primary_occupations,
+                names,
+                s.image.and_then(|i| i.medium),
+            )
+        })
+        .collect::<Result<Vec<_>, ServiceError>>()?;
+
+    SearchResultsTemplate {
+        search_info: CompletedSearchInfo {
+            num_found_results: i64::try_from(staff_ids.len())?,
+            num_results_on_page: u32::try_from(search_results.len())?,
+        },
+        search_results,
+        studio_results: Vec::new(),
+    }
+    .to_response()
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_staff_opml);
+    cfg.service(get_bundle_opml);
+    cfg.service(opml_import_form);
+    cfg.service(import_opml);
+}
@@ -1,5 +1,4 @@
 use super::super::{errors, errors::ServiceError};
-use crate::clients::search_query;
 use crate::AppData;
 use actix_web::{
     body::BoxBody, get, http::header::ContentType, http::StatusCode, web, HttpRequest,
@@ -8,7 +7,8 @@ use actix_web::{
 use askama::Template;
 use serde::Deserialize;
 
-const MAX_ANILIST_STAFF_SEARCH_RESULTS: i64 = 50;
+/// Per kind of result, so up to this many staff and this many studios.
+const MAX_ANILIST_SEARCH_RESULTS: i64 = 50;
 
 pub trait TemplateToResponse {
     fn to_response(&self) -> Result<HttpResponse<BoxBody>, ServiceError>;
@@ -25,10 +25,6 @@ impl<T: Template> TemplateToResponse for T {
     }
 }
 
-#[derive(Template)]
-#[template(path = "base.html")]
-struct BaseTemplate {}
-
 #[derive(Template)]
 #[template(path = "index.html")]
 struct IndexTemplate {}
@@ -40,24 +36,34 @@ async fn index(_req: HttpRequest) -> Result<HttpResponse<BoxBody>, ServiceError>
     template.to_response()
 }
 
-struct SearchResult {
+pub(super) struct SearchResult {
     primary_occupations: Vec<String>,
     show_name: String,
     image_link: String,
     rss_link: String,
+    atom_link: String,
+    source_link: String,
+}
+
+pub(super) struct StudioSearchResult {
+    name: String,
+    kind: &'static str,
+    rss_link: String,
+    atom_link: String,
     source_link: String,
 }
 
-struct CompletedSearchInfo {
-    num_found_results: i64,
-    num_results_on_page: u32,
+pub(super) struct CompletedSearchInfo {
+    pub(super) num_found_results: i64,
+    pub(super) num_results_on_page: u32,
 }
 
 #[derive(Template)]
 #[template(path = "search_results.html")]
-struct SearchResultsTemplate {
-    search_info: CompletedSearchInfo,
-    search_results: Vec<SearchResult>,
+pub(super) struct SearchResultsTemplate {
+    pub(super) search_info: CompletedSearchInfo,
+    pub(super) search_results: Vec<SearchResult>,
+    pub(super) studio_results: Vec<StudioSearchResult>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -75,10 +81,11 @@ async fn search_results(
         Some(v) => v,
         None => "".to_string(),
     };
-    let client = &shared_data.anilist_client;
-    let staff = client
-        .search(&q, MAX_ANILIST_STAFF_SEARCH_RESULTS)
-        .await?
+    let search = shared_data
+        .anilist_repository
+        .search(&q, MAX_ANILIST_SEARCH_RESULTS)
+        .await?;
+    let staff = search
         .staff
         .ok_or(errors::anilist_data_format("SearchQueryStaff is None"))?;
     let staff_results = staff
@@ -87,43 +94,21 @@ async fn search_results(
             "SearchQueryStafffResults is None",
         ))?
         .into_iter()
-        .filter_map(|row| row)
+        .flatten()
         .map(|row| {
-            let primary_occupations = row
-                .primary_occupations
-                .ok_or(errors::anilist_data_format(
-                    "Staff.results.primary_occupations is None",
-                ))?
-                .into_iter()
-                .filter_map(|o| o)
-                .map(|o| o.trim().to_string())
-                .collect();
             let name = row.name.ok_or(errors::anilist_data_format(
                 "Staff.results.name not provided for Staff",
             ))?;
-            let staff_name_collection: Vec<Option<String>> = vec![name.full, name.native];
-            let show_name: String = staff_name_collection
-                .into_iter()
-                .filter_map(|n| n)
-                .collect::<Vec<String>>()
-                .join(", ");
             let image_link = row
                 .image
                 .ok_or(errors::anilist_data_format("Staff.results.image is None"))?
-                .medium
-                .ok_or(errors::anilist_data_format(
-                    "Staff.results.image.medium is None",
-                ))?;
-            let rss_link = anilist_rss_link(row.id);
-            let source_link = anilist_staff_link(row.id);
-
-            Ok(SearchResult {
-                primary_occupations,
-                show_name,
+                .medium;
+            search_result(
+                row.id,
+                row.primary_occupations,
+                vec![name.full, name.native],
                 image_link,
-                rss_link,
-                source_link,
-            })
+            )
         })
         .collect::<Result<Vec<SearchResult>, ServiceError>>()?;
     let page_info = staff
@@ -137,19 +122,89 @@ async fn search_results(
         num_results_on_page: u32::try_from(staff_results.len())?,
     };
 
+    let studio_results = search
+        .studios
+        .and_then(|s| s.results)
+        .unwrap_or_default()
+        .into_iter()
+        .flatten()
+        .map(|row| StudioSearchResult {
+            name: row.name,
+            kind: if row.is_animation_studio {
+                "Animation studio"
+            } else {
+                "Studio"
+            },
+            rss_link: anilist_studio_rss_link(row.id),
+            atom_link: anilist_studio_atom_link(row.id),
+            source_link: anilist_studio_link(row.id),
+        })
+        .collect();
+
     let template = SearchResultsTemplate {
         search_info,
         search_results: staff_results,
+        studio_results,
     };
     template.to_response()
 }
 
-fn anilist_rss_link(staff_id: i64) -> String {
-    return format!("/rss/anilist/staff/{staff_id}").to_string();
+/// One row of the search results page, shared by search and OPML import.
+pub(super) fn search_result(
+    id: i64,
+    primary_occupations: Option<Vec<Option<String>>>,
+    names: Vec<Option<String>>,
+    image_link: Option<String>,
+) -> Result<SearchResult, ServiceError> {
+    let primary_occupations = primary_occupations
+        .ok_or(errors::anilist_data_format(
+            "Staff.results.primary_occupations is None",
+        ))?
+        .into_iter()
+        .flatten()
+        .map(|o| o.trim().to_string())
+        .collect();
+    let show_name: String = names
+        .into_iter()
+        .flatten()
+        .collect::<Vec<String>>()
+        .join(", ");
+    let image_link = image_link.ok_or(errors::anilist_data_format(
+        "Staff.results.image.medium is None",
+    ))?;
+
+    Ok(SearchResult {
+        primary_occupations,
+        show_name,
+        image_link,
+        rss_link: anilist_rss_link(id),
+        atom_link: anilist_atom_link(id),
+        source_link: anilist_staff_link(id),
+    })
+}
+
+pub(super) fn anilist_rss_link(staff_id: i64) -> String {
+    format!("/rss/anilist/staff/{staff_id}")
+}
+
+fn anilist_atom_link(staff_id: i64) -> String {
+    format!("/atom/anilist/staff/{staff_id}")
+}
+
+pub(super) fn anilist_staff_link(staff_id: i64) -> String {
+    format!("https://anilist.co/staff/{staff_id}")
+}
+
+fn anilist_studio_rss_link(studio_id: i64) -> String {
+    format!("/rss/anilist/studio/{studio_id}")
+}
+
+fn anilist_studio_atom_link(studio_id: i64) -> String {
+    format!("/atom/anilist/studio/{studio_id}")
 }
 
-fn anilist_staff_link(staff_id: i64) -> String {
-    return format!("https://anilist.co/staff/{staff_id}").to_string();
+fn anilist_studio_link(studio_id: i64) -> String {
+    format!("https://anilist.co/studio/{studio_id}")
 }
 
 pub fn init(cfg: &mut web::ServiceConfig) {
@@ -0,0 +1,153 @@
+use crate::errors::{ServiceError, SharedServiceError};
+use futures::future::{BoxFuture, FutureExt, Shared};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex, PoisonError};
+
+type SharedFlight<V> = Shared<BoxFuture<'static, Result<Arc<V>, SharedServiceError>>>;
+
+/// Deduplicates concurrent work by key: while a value for a key is being
+/// built, everyone else asking for the same key waits on that build and gets
+/// the same result. Nothing is kept once the build is done.
+pub struct SingleFlight<V> {
+    in_flight: Mutex<HashMap<String, SharedFlight<V>>>,
+}
+
+impl<V: Send + Sync + 'static> SingleFlight<V> {
+    pub fn new() -> Self {
+        SingleFlight {
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Runs `build` unless a build for `key` is already in flight, in which
+    /// case that one is waited on instead.
+    pub async fn run<F, Fut>(&self, key: String, build: F) -> Result<Arc<V>, ServiceError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, ServiceError>> + Send + 'static,
+    {
+        let guard = {
+            let mut in_flight = self
+                .in_flight
+                .lock()
+                .expect("Single flight lock is poisoned");
+            let flight = match in_flight.get(&key) {
+                Some(flight) => {
+                    info!("Joining in flight build: {}", key);
+                    flight.clone()
+                }
+                None => {
+                    let flight = build()
+                        .map(|result| result.map(Arc::new).map_err(SharedServiceError::from))
+                        .boxed()
+                        .shared();
+                    in_flight.insert(key.clone(), flight.clone());
+                    flight
+                }
+            };
+            FlightGuard {
+                in_flight: &self.in_flight,
+                key,
+                flight,
+            }
+        };
+
+        let result = guard.flight.clone().await;
+        result.map_err(ServiceError::from)
+    }
+}
+
+/// A waiter's hold on a flight. Dropping it takes the flight out of
+/// `in_flight` once the build is done, or when the request waiting on it went
+/// away and nobody else waits, so no half polled build is left behind for
+/// later requests to join.
+struct FlightGuard<'a, V> {
+    in_flight: &'a Mutex<HashMap<String, SharedFlight<V>>>,
+    key: String,
+    flight: SharedFlight<V>,
+}
+
+impl<V> Drop for FlightGuard<'_, V> {
+    fn drop(&mut self) {
+        let mut in_flight = self
+            .in_flight
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        // Once nobody else waits, this guard and `in_flight` hold the last two
+        // handles on the build.
+        let unwaited = self.flight.peek().is_some()
+            || self.flight.strong_count().is_some_and(|count| count <= 2);
+        if unwaited
+            && in_flight
+                .get(&self.key)
+                .is_some_and(|current| current.ptr_eq(&self.flight))
+        {
+            in_flight.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::rt::time;
+    use futures::future;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    fn in_flight_count<V>(single_flight: &SingleFlight<V>) -> usize {
+        single_flight.in_flight.lock().unwrap().len()
+    }
+
+    #[actix_web::test]
+    async fn concurrent_runs_share_one_build() {
+        let single_flight = SingleFlight::new();
+        let builds = Arc::new(AtomicUsize::new(0));
+        let run = || {
+            let builds = builds.clone();
+            single_flight.run("key".to_string(), move || async move {
+                builds.fetch_add(1, Ordering::SeqCst);
+                time::sleep(Duration::from_millis(10)).await;
+                Ok(42)
+            })
+        };
+
+        let (first, second) = future::join(run(), run()).await;
+        assert_eq!(*first.unwrap(), 42);
+        assert_eq!(*second.unwrap(), 42);
+        assert_eq!(builds.load(Ordering::SeqCst), 1);
+        assert_eq!(in_flight_count(&single_flight), 0);
+    }
+
+    #[actix_web::test]
+    async fn abandoned_build_is_not_joined_later() {
+        let single_flight = SingleFlight::<i64>::new();
+        let abandoned = single_flight.run("key".to_string(), future::pending);
+        assert!(time::timeout(Duration::from_millis(10), abandoned)
+            .await
+            .is_err());
+        assert_eq!(in_flight_count(&single_flight), 0);
+
+        let result = single_flight
+            .run("key".to_string(), || async { Ok(7) })
+            .await;
+        assert_eq!(*result.unwrap(), 7);
+    }
+
+    #[actix_web::test]
+    async fn build_stays_while_someone_still_waits() {
+        let single_flight = SingleFlight::<i64>::new();
+        let waiting = single_flight.run("key".to_string(), || async {
+            time::sleep(Duration::from_millis(20)).await;
+            Ok(1)
+        });
+        let abandoned = single_flight.run("key".to_string(), || async { Ok(2) });
+
+        let (result, timed_out) =
+            future::join(waiting, time::timeout(Duration::from_millis(5), abandoned)).await;
+        assert!(timed_out.is_err());
+        assert_eq!(*result.unwrap(), 1);
+        assert_eq!(in_flight_count(&single_flight), 0);
+    }
+}
@@ -0,0 +1,719 @@
+use crate::{
+    bundles::{self, Bundle, BundleInput},
+    errors,
+    errors::ServiceError,
+    models::{
+        FuzzyDate, Media, MediaChange, MediaChangeKind, Staff, StaffCredit, StaffFilmography,
+    },
+};
+use chrono::{DateTime, Utc};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions},
+    FromRow, Sqlite, Transaction,
+};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// SQLite backed copy of everything fetched from Anilist.
+#[derive(Clone)]
+pub struct Storage {
+    pool: SqlitePool,
+}
+
+#[derive(FromRow)]
+struct StaffRow {
+    id: i64,
+    name_full: Option<String>,
+    name_native: Option<String>,
+    site_url: Option<String>,
+    description: Option<String>,
+    image_large: Option<String>,
+    fetched_at: DateTime<Utc>,
+    first_fetched_at: Option<DateTime<Utc>>,
+}
+
+#[derive(FromRow)]
+struct CreditRow {
+    role: String,
+    first_seen_at: DateTime<Utc>,
+    media_id: i64,
+    title_romaji: Option<String>,
+    title_english: Option<String>,
+    title_native: Option<String>,
+    media_type: Option<String>,
+    format: Option<String>,
+    status: Option<String>,
+    description: Option<String>,
+    site_url: Option<String>,
+    start_year: Option<i64>,
+    start_month: Option<i64>,
+    start_day: Option<i64>,
+}
+
+#[derive(FromRow)]
+struct MediaStateRow {
+    status: Option<String>,
+    start_year: Option<i64>,
+    start_month: Option<i64>,
+    start_day: Option<i64>,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(FromRow)]
+struct MediaChangeRow {
+    id: i64,
+    media_id: i64,
+    kind: String,
+    old_value: Option<String>,
+    new_value: Option<String>,
+    changed_at: DateTime<Utc>,
+}
+
+#[derive(FromRow)]
+struct BundleRow {
+    slug: String,
+    name: String,
+    feed_options: String,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl Storage {
+    /// Opens, creating if needed, the database at `database_url` and brings its
+    /// schema up to date.
+    pub async fn connect(database_url: &str) -> Result<Storage, sqlx::Error> {
+        let options = SqliteConnectOptions::from_str(database_url)?
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .foreign_keys(true);
+        let pool = SqlitePoolOptions::new().connect_with(options).await?;
+        sqlx::migrate!().run(&pool).await?;
+
+        Ok(Storage { pool })
+    }
+
+    /// Upserts the staff, their media and credits. Credits keep their original
+    /// `first_seen_at`, everything else is overwritten with the fresh copy after
+    /// recording any change to a stored media's status or start date.
+    ///
+    /// `fetched_at` is when Anilist sent the filmography, which for a cached
+    /// response can be behind what is stored. Nothing is written when the staff
+    /// was stored from a copy at least as recent, and media stored from a more
+    /// recent copy, through another staff, are left as they are.
+    pub async fn save_staff_filmography(
+        &self,
+        filmography: &StaffFilmography,
+        fetched_at: DateTime<Utc>,
+    ) -> Result<(), ServiceError> {
+        let staff = &filmography.staff;
+        let mut transaction = self.pool.begin().await?;
+
+        let stored_fetched_at: Option<DateTime<Utc>> =
+            sqlx::query_scalar("SELECT fetched_at FROM anilist_staff WHERE id = ?")
+                .bind(staff.id)
+                .fetch_optional(&mut *transaction)
+                .await?;
+        if stored_fetched_at.is_some_and(|stored| stored >= fetched_at) {
+            return Ok(());
+        }
+
+        sqlx::query(
+            "INSERT INTO anilist_staff
+                (id, name_full, name_native, site_url, description, image_large, fetched_at,
+                first_fetched_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (id) DO UPDATE SET
+                name_full = excluded.name_full,
+                name_native = excluded.name_native,
+                site_url = excluded.site_url,
+                description = excluded.description,
+                image_large = excluded.image_large,
+                fetched_at = excluded.fetched_at",
+        )
+        .bind(staff.id)
+        .bind(&staff.name_full)
+        .bind(&staff.name_native)
+        .bind(&staff.site_url)
+        .bind(&staff.description)
+        .bind(&staff.image_large)
+        .bind(fetched_at)
+        .bind(fetched_at)
+        .execute(&mut *transaction)
+        .await?;
+
+        for (position, credit) in filmography.credits.iter().enumerate() {
+            let media = &credit.media;
+            let previous: Option<MediaStateRow> = sqlx::query_as(
+                "SELECT status, start_year, start_month, start_day, updated_at
+                FROM anilist_media
+                WHERE id = ?",
+            )
+            .bind(media.id)
+            .fetch_optional(&mut *transaction)
+            .await?;
+            if let Some(previous) = previous.filter(|p| p.updated_at <= fetched_at) {
+                for (kind, old_value, new_value) in media_state_changes(&previous, media) {
+                    sqlx::query(
+                        "INSERT INTO anilist_media_changes
+                            (media_id, kind, old_value, new_value, changed_at)
+                        VALUES (?, ?, ?, ?, ?)",
+                    )
+                    .bind(media.id)
+                    .bind(kind.as_str())
+                    .bind(old_value)
+                    .bind(new_value)
+                    .bind(fetched_at)
+                    .execute(&mut *transaction)
+                    .await?;
+                }
+            }
+
+            sqlx::query(
+                "INSERT INTO anilist_media
+                    (id, title_romaji, title_english, title_native, media_type, format, status,
+                    description, site_url, start_year, start_month, start_day, updated_at)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                ON CONFLICT (id) DO UPDATE SET
+                    title_romaji = excluded.title_romaji,
+                    title_english = excluded.title_english,
+                    title_native = excluded.title_native,
+                    media_type = excluded.media_type,
+                    format = excluded.format,
+                    status = excluded.status,
+                    description = excluded.description,
+                    site_url = excluded.site_url,
+                    start_year = excluded.start_year,
+                    start_month = excluded.start_month,
+                    start_day = excluded.start_day,
+                    updated_at = excluded.updated_at
+                WHERE anilist_media.updated_at <= excluded.updated_at",
+            )
+            .bind(media.id)
+            .bind(&media.title_romaji)
+            .bind(&media.title_english)
+            .bind(&media.title_native)
+            .bind(&media.media_type)
+            .bind(&media.format)
+            .bind(&media.status)
+            .bind(&media.description)
+            .bind(&media.site_url)
+            .bind(media.start_date.year)
+            .bind(media.start_date.month)
+            .bind(media.start_date.day)
+            .bind(fetched_at)
+            .execute(&mut *transaction)
+            .await?;
+
+            sqlx::query(
+                "INSERT INTO anilist_staff_media_roles
+                    (staff_id, media_id, role, position, first_seen_at, last_seen_at)
+                VALUES (?, ?, ?, ?, ?, ?)
+                ON CONFLICT (staff_id, media_id, role) DO UPDATE SET
+                    position = excluded.position,
+                    last_seen_at = excluded.last_seen_at",
+            )
+            .bind(staff.id)
+            .bind(media.id)
+            .bind(&credit.role)
+            .bind(position as i64)
+            .bind(fetched_at)
+            .bind(fetched_at)
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Loads the staff, the credits Anilist listed on their latest fetch and the
+    /// changes recorded for those credits' media.
+    pub async fn load_staff_filmography(
+        &self,
+        staff_id: i64,
+    ) -> Result<Option<StaffFilmography>, ServiceError> {
+        let staff_row: Option<StaffRow> = sqlx::query_as(
+            "SELECT id, name_full, name_native, site_url, description, image_large, fetched_at,
+                first_fetched_at
+            FROM anilist_staff
+            WHERE id = ?",
+        )
+        .bind(staff_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        let staff_row = match staff_row {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+
+        let credit_rows: Vec<CreditRow> = sqlx::query_as(
+            "SELECT r.role, r.first_seen_at, m.id AS media_id, m.title_romaji, m.title_english,
+                m.title_native, m.media_type, m.format, m.status, m.description, m.site_url,
+                m.start_year, m.start_month, m.start_day
+            FROM anilist_staff_media_roles r
+            JOIN anilist_media m ON m.id = r.media_id
+            WHERE r.staff_id = ? AND r.last_seen_at = ?
+            ORDER BY r.position",
+        )
+        .bind(staff_id)
+        .bind(staff_row.fetched_at)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let change_rows: Vec<MediaChangeRow> = sqlx::query_as(
+            "SELECT c.id, c.media_id, c.kind, c.old_value, c.new_value, c.changed_at
+            FROM anilist_media_changes c
+            WHERE c.media_id IN (
+                SELECT r.media_id
+                FROM anilist_staff_media_roles r
+                WHERE r.staff_id = ? AND r.last_seen_at = ?
+            )
+            ORDER BY c.changed_at, c.id",
+        )
+        .bind(staff_id)
+        .bind(staff_row.fetched_at)
+        .fetch_all(&self.pool)
+        .await?;
+        let media_changes = change_rows
+            .into_iter()
+            .map(MediaChange::try_from)
+            .collect::<Result<Vec<MediaChange>, ServiceError>>()?;
+
+        Ok(Some(StaffFilmography {
+            staff: Staff {
+                id: staff_row.id,
+                name_full: staff_row.name_full,
+                name_native: staff_row.name_native,
+                site_url: staff_row.site_url,
+                description: staff_row.description,
+                image_large: staff_row.image_large,
+            },
+            credits: credit_rows.into_iter().map(StaffCredit::from).collect(),
+            first_fetched_at: staff_row.first_fetched_at,
+            fetched_at: Some(staff_row.fetched_at),
+            media_changes,
+        }))
+    }
+
+    /// When each of the given media, which have no start year, was first seen.
+    /// Media seen for the first time are recorded as seen at `now`.
+    pub async fn undated_media_first_seen(
+        &self,
+        media_ids: &[i64],
+        now: DateTime<Utc>,
+    ) -> Result<HashMap<i64, DateTime<Utc>>, ServiceError> {
+        let mut first_seen: HashMap<i64, DateTime<Utc>> = HashMap::with_capacity(media_ids.len());
+        let mut transaction = self.pool.begin().await?;
+        for &media_id in media_ids {
+            sqlx::query(
+                "INSERT INTO anilist_undated_media (media_id, first_seen_at)
+                VALUES (?, ?)
+                ON CONFLICT (media_id) DO NOTHING",
+            )
+            .bind(media_id)
+            .bind(now)
+            .execute(&mut *transaction)
+            .await?;
+            let first_seen_at: DateTime<Utc> = sqlx::query_scalar(
+                "SELECT first_seen_at FROM anilist_undated_media WHERE media_id = ?",
+            )
+            .bind(media_id)
+            .fetch_one(&mut *transaction)
+            .await?;
+            first_seen.insert(media_id, first_seen_at);
+        }
+        transaction.commit().await?;
+        Ok(first_seen)
+    }
+
+    /// Saves a new bundle, failing if its slug is already taken.
+    pub async fn create_bundle(
+        &self,
+        slug: &str,
+        input: &BundleInput,
+        edit_token_hash: &str,
+        now: DateTime<Utc>,
+    ) -> Result<(), ServiceError> {
+        let feed_options = bundles::options_to_query(&input.options)?;
+        let mut transaction = self.pool.begin().await?;
+        let inserted = sqlx::query(
+            "INSERT INTO bundles
+                (slug, name, feed_options, edit_token_sha256, created_at, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(slug)
+        .bind(&input.name)
+        .bind(&feed_options)
+        .bind(edit_token_hash)
+        .bind(now)
+        .bind(now)
+        .execute(&mut *transaction)
+        .await;
+        match inserted {
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                return Err(errors::invalid_parameter(
+                    "slug",
+                    "is already taken".to_string(),
+                ))
+            }
+            inserted => inserted?,
+        };
+        insert_bundle_staff(&mut transaction, slug, &input.staff_ids).await?;
+
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Replaces a bundle's name, staff and options as long as `edit_token_hash`
+    /// matches the one it was created with.
+    pub async fn update_bundle(
+        &self,
+        slug: &str,
+        input: &BundleInput,
+        edit_token_hash: &str,
+        now: DateTime<Utc>,
+    ) -> Result<(), ServiceError> {
+        let feed_options = bundles::options_to_query(&input.options)?;
+        let mut transaction = self.pool.begin().await?;
+        let stored_hash: Option<String> =
+            sqlx::query_scalar("SELECT edit_token_sha256 FROM bundles WHERE slug = ?")
+                .bind(slug)
+                .fetch_optional(&mut *transaction)
+                .await?;
+        match stored_hash {
+            None => return Err(errors::not_found("No bundle with this slug")),
+            Some(stored_hash) if stored_hash != edit_token_hash => {
+                return Err(errors::forbidden("Edit token doesn't match this bundle"))
+            }
+            Some(_) => {}
+        }
+
+        sqlx::query(
+            "UPDATE bundles
+            SET name = ?, feed_options = ?, updated_at = ?
+            WHERE slug = ?",
+        )
+        .bind(&input.name)
+        .bind(&feed_options)
+        .bind(now)
+        .bind(slug)
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query("DELETE FROM bundle_staff WHERE bundle_slug = ?")
+            .bind(slug)
+            .execute(&mut *transaction)
+            .await?;
+        insert_bundle_staff(&mut transaction, slug, &input.staff_ids).await?;
+
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    pub async fn load_bundle(&self, slug: &str) -> Result<Option<Bundle>, ServiceError> {
+        let bundle_row: Option<BundleRow> = sqlx::query_as(
+            "SELECT slug, name, feed_options, created_at, updated_at
+            FROM bundles
+            WHERE slug = ?",
+        )
+        .bind(slug)
+        .fetch_optional(&self.pool)
+        .await?;
+        let bundle_row = match bundle_row {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+
+        let staff_ids: Vec<i64> = sqlx::query_scalar(
+            "SELECT staff_id FROM bundle_staff WHERE bundle_slug = ? ORDER BY position",
+        )
+        .bind(slug)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(Some(Bundle {
+            slug: bundle_row.slug,
+            name: bundle_row.name,
+            staff_ids,
+            options: bundles::options_from_query(&bundle_row.feed_options)?,
+            created_at: bundle_row.created_at,
+            updated_at: bundle_row.updated_at,
+        }))
+    }
+}
+
+async fn insert_bundle_staff(
+    transaction: &mut Transaction<'_, Sqlite>,
+    slug: &str,
+    staff_ids: &[i64],
+) -> Result<(), ServiceError> {
+    for (position, staff_id) in staff_ids.iter().enumerate() {
+        sqlx::query("INSERT INTO bundle_staff (bundle_slug, staff_id, position) VALUES (?, ?, ?)")
+            .bind(slug)
+            .bind(staff_id)
+            .bind(position as i64)
+            .execute(&mut **transaction)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Status and start date differences between the stored and the fresh media.
+fn media_state_changes(
+    previous: &MediaStateRow,
+    media: &Media,
+) -> Vec<(MediaChangeKind, Option<String>, Option<String>)> {
+    let mut changes = Vec::new();
+    if previous.status != media.status {
+        changes.push((
+            MediaChangeKind::Status,
+            previous.status.clone(),
+            media.status.clone(),
+        ));
+    }
+    let previous_start_date = FuzzyDate {
+        year: previous.start_year,
+        month: previous.start_month,
+        day: previous.start_day,
+    };
+    if previous_start_date != media.start_date {
+        changes.push((
+            MediaChangeKind::StartDate,
+            previous_start_date.to_iso(),
+            media.start_date.to_iso(),
+        ));
+    }
+    changes
+}
+
+impl From<CreditRow> for StaffCredit {
+    fn from(row: CreditRow) -> StaffCredit {
+        StaffCredit {
+            role: row.role,
+            first_seen_at: Some(row.first_seen_at),
+            media: Media {
+                id: row.media_id,
+                title_romaji: row.title_romaji,
+                title_english: row.title_english,
+                title_native: row.title_native,
+                media_type: row.media_type,
+                format: row.format,
+                status: row.status,
+                description: row.description,
+                site_url: row.site_url,
+                start_date: FuzzyDate {
+                    year: row.start_year,
+                    month: row.start_month,
+                    day: row.start_day,
+                },
+            },
+        }
+    }
+}
+
+impl TryFrom<MediaChangeRow> for MediaChange {
+    type Error = ServiceError;
+
+    fn try_from(row: MediaChangeRow) -> Result<MediaChange, ServiceError> {
+        let kind = MediaChangeKind::from_name(&row.kind).ok_or(errors::internal_logic_error(
+            "anilist_media_changes.kind is not a known MediaChangeKind",
+        ))?;
+        Ok(MediaChange {
+            id: row.id,
+            media_id: row.media_id,
+            kind,
+            old_value: row.old_value,
+            new_value: row.new_value,
+            changed_at: row.changed_at,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+    use std::path::PathBuf;
+
+    fn media(id: i64, status: &str, year: i64, month: Option<i64>) -> Media {
+        Media {
+            id,
+            title_romaji: Some(format!("Media {}", id)),
+            title_english: None,
+            title_native: None,
+            media_type: Some("ANIME".to_string()),
+            format: Some("TV".to_string()),
+            status: Some(status.to_string()),
+            description: None,
+            site_url: None,
+            start_date: FuzzyDate {
+                year: Some(year),
+                month,
+                day: None,
+            },
+        }
+    }
+
+    fn filmography(staff_id: i64, credits: Vec<Media>) -> StaffFilmography {
+        StaffFilmography {
+            staff: Staff {
+                id: staff_id,
+                name_full: Some("Staff".to_string()),
+                name_native: None,
+                site_url: None,
+                description: None,
+                image_large: None,
+            },
+            credits: credits
+                .into_iter()
+                .map(|media| StaffCredit {
+                    role: "Director".to_string(),
+                    media,
+                    first_seen_at: None,
+                })
+                .collect(),
+            first_fetched_at: None,
+            fetched_at: None,
+            media_changes: Vec::new(),
+        }
+    }
+
+    /// A database file of its own, removed along with its WAL files on drop.
+    struct TestDatabase(PathBuf);
+
+    impl TestDatabase {
+        fn new() -> TestDatabase {
+            TestDatabase(
+                std::env::temp_dir().join(format!("cf-server-storage-{}.db", fastrand::u64(..))),
+            )
+        }
+
+        async fn connect(&self) -> Storage {
+            Storage::connect(&format!("sqlite://{}", self.0.display()))
+                .await
+                .unwrap()
+        }
+    }
+
+    impl Drop for TestDatabase {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let mut path = self.0.clone().into_os_string();
+                path.push(suffix);
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    #[test]
+    fn media_state_changes_is_empty_for_the_same_state() {
+        let previous = MediaStateRow {
+            status: Some("RELEASING".to_string()),
+            start_year: Some(2024),
+            start_month: Some(4),
+            start_day: None,
+            updated_at: Utc::now(),
+        };
+        assert!(media_state_changes(&previous, &media(1, "RELEASING", 2024, Some(4))).is_empty());
+    }
+
+    #[test]
+    fn media_state_changes_lists_status_and_start_date() {
+        let previous = MediaStateRow {
+            status: Some("NOT_YET_RELEASED".to_string()),
+            start_year: Some(2024),
+            start_month: None,
+            start_day: None,
+            updated_at: Utc::now(),
+        };
+        let changes = media_state_changes(&previous, &media(1, "RELEASING", 2024, Some(4)));
+        assert_eq!(
+            changes,
+            vec![
+                (
+                    MediaChangeKind::Status,
+                    Some("NOT_YET_RELEASED".to_string()),
+                    Some("RELEASING".to_string())
+                ),
+                (
+                    MediaChangeKind::StartDate,
+                    Some("2024".to_string()),
+                    Some("2024-04".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn older_snapshot_neither_overwrites_nor_diffs() {
+        let database = TestDatabase::new();
+        let storage = database.connect().await;
+        let newer = Utc.with_ymd_and_hms(2026, 10, 18, 12, 0, 0).unwrap();
+        let older = newer - Duration::hours(1);
+
+        storage
+            .save_staff_filmography(
+                &filmography(1, vec![media(1, "RELEASING", 2024, None)]),
+                newer,
+            )
+            .await
+            .unwrap();
+        storage
+            .save_staff_filmography(
+                &filmography(1, vec![media(1, "NOT_YET_RELEASED", 2024, None)]),
+                older,
+            )
+            .await
+            .unwrap();
+
+        let stored = storage.load_staff_filmography(1).await.unwrap().unwrap();
+        assert_eq!(stored.fetched_at, Some(newer));
+        assert_eq!(stored.credits[0].media.status.as_deref(), Some("RELEASING"));
+        assert!(stored.media_changes.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn older_snapshot_keeps_media_stored_through_another_staff() {
+        let database = TestDatabase::new();
+        let storage = database.connect().await;
+        let newer = Utc.with_ymd_and_hms(2026, 10, 18, 12, 0, 0).unwrap();
+        let older = newer - Duration::hours(1);
+
+        storage
+            .save_staff_filmography(
+                &filmography(2, vec![media(1, "RELEASING", 2024, None)]),
+                newer,
+            )
+            .await
+            .unwrap();
+        storage
+            .save_staff_filmography(
+                &filmography(1, vec![media(1, "NOT_YET_RELEASED", 2024, None)]),
+                older,
+            )
+            .await
+            .unwrap();
+
+        let stored = storage.load_staff_filmography(1).await.unwrap().unwrap();
+        assert_eq!(stored.fetched_at, Some(older));
+        assert_eq!(stored.credits[0].media.status.as_deref(), Some("RELEASING"));
+        assert!(stored.media_changes.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn undated_media_keep_their_first_seen_date() {
+        let database = TestDatabase::new();
+        let storage = database.connect().await;
+        let first = Utc.with_ymd_and_hms(2026, 10, 1, 0, 0, 0).unwrap();
+        let later = first + Duration::days(7);
+
+        let seen = storage.undated_media_first_seen(&[1], first).await.unwrap();
+        assert_eq!(seen.get(&1), Some(&first));
+
+        let seen = storage
+            .undated_media_first_seen(&[1, 2], later)
+            .await
+            .unwrap();
+        assert_eq!(seen.get(&1), Some(&first));
+        assert_eq!(seen.get(&2), Some(&later));
+    }
+}